@@ -1,7 +1,18 @@
-use crate::output::OutputBuffer;
+use crate::{
+    gitignore::{GitIgnore, GitIgnoreMode},
+    output::{Hyperlinks, OutputBuffer},
+    style::LsColors,
+    time::TimeStyle,
+    utils::BlockSize,
+    xattr::XattrListing,
+};
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
 use veneer::{env, syscalls::*, CStr, Error};
 
+/// Set once statx fails with ENOSYS, after which we use fstatat
+static STATX_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+
 macro_rules! error {
     ($($item:expr),+) => {
         {
@@ -14,24 +25,59 @@ macro_rules! error {
 
 pub struct App {
     pub print_inode: bool,
+    /// -Z, which shows each entry's SELinux context
+    pub print_context: bool,
+    /// --capabilities, which adds a column with each entry's file capabilities in long format
+    pub print_capabilities: bool,
     pub block_size_is_kilobytes: bool,
+    /// Units for -s and the total line
+    pub block_size: BlockSize,
+    /// Units for the size column in long format
+    pub file_block_size: BlockSize,
+    block_size_arg: Option<BlockSize>,
     pub replace_unprintable_bytes: bool,
+    quoting_style_arg: Option<QuotingStyle>,
     pub reverse_sorting: bool,
     pub grid_sort_direction: SortDirection,
     pub display_size_in_blocks: bool,
     pub display_mode: DisplayMode,
     pub show_all: ShowAll,
+    /// Names matching these are never listed, from -I and -B
+    ignore_patterns: Vec<&'static [u8]>,
+    /// Like ignore_patterns, but -a and -A show them anyway
+    hide_patterns: Vec<&'static [u8]>,
+    /// Rules from .gitignore files and friends for --git-ignore
+    pub git_ignore: Option<GitIgnore>,
+    git_ignore_arg: Option<GitIgnoreMode>,
+    /// --git, which adds a column with each entry's status
+    pub git_status: bool,
+    pub git_repository: Option<crate::git::Repository>,
+    /// --xattrs, which lists extended attributes under each entry in long format
+    pub xattrs: Option<XattrListing>,
     pub suffixes: Suffixes,
     pub follow_symlinks: FollowSymlinks,
     pub recurse: bool,
-    pub sort_field: Option<SortField>,
+    /// None leaves entries in directory order. Otherwise entries are sorted by each key in turn,
+    /// then by name.
+    pub sort_keys: Option<Vec<SortField>>,
+    pub group_directories_first: bool,
+    tree: bool,
+    /// How many levels of directories --tree descends into
+    pub tree_level: Option<usize>,
+    /// Draw the --tree guides with |-- and `-- instead of box-drawing characters
+    pub ascii_guides: bool,
     pub time_field: TimeField,
+    pub time_style: TimeStyle,
+    time_style_arg: Option<TimeStyle>,
     pub list_directory_contents: bool,
     pub out: OutputBuffer,
     pub convert_id_to_name: bool,
     pub print_owner: bool,
     pub print_group: bool,
     pub color: Color,
+    /// --hyperlink takes the same always, auto and never words as --color
+    hyperlink: Color,
+    pub ls_colors: LsColors,
 
     pub args: Args,
 
@@ -40,9 +86,16 @@ pub struct App {
     etc_group: &'static [u8],
     gid_names: Vec<(u32, (usize, usize))>,
 
-    tzinfo: Option<crate::time::Tzinfo>,
+    /// Leaked like environ, so that the zone names in a LocalTime don't borrow the App
+    tzinfo: Option<&'static crate::time::Tzinfo>,
+
+    environ: Option<&'static [u8]>,
 
     pub needs_details: bool,
+    /// --version, which is handled after all the arguments are parsed
+    print_version: bool,
+    /// The fields statx has to fill in for the options we were given
+    statx_mask: u32,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -57,6 +110,19 @@ pub enum TimeField {
     Accessed,
     Modified,
     StatusChanged,
+    /// Not every filesystem has one, and without statx we never know it
+    Birth,
+}
+
+impl TimeField {
+    fn statx_mask(self) -> u32 {
+        match self {
+            TimeField::Accessed => libc::STATX_ATIME,
+            TimeField::Modified => libc::STATX_MTIME,
+            TimeField::StatusChanged => libc::STATX_CTIME,
+            TimeField::Birth => libc::STATX_BTIME,
+        }
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -64,6 +130,9 @@ pub enum SortField {
     Name,
     Size,
     Time,
+    Extension,
+    Width,
+    Inode,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -93,34 +162,81 @@ pub enum SortDirection {
     Vertical,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum QuotingStyle {
+    Literal,
+    Shell,
+    ShellAlways,
+    ShellEscape,
+    ShellEscapeAlways,
+    C,
+    Escape,
+    Locale,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum DisplayMode {
     Grid(usize),
     Long,
     SingleColumn,
     Stream,
+    /// --tree, which is also in long format with -l
+    Tree {
+        long: bool,
+    },
+    /// --format=json, or --format=ndjson with one object per line
+    Json {
+        lines: bool,
+    },
+}
+
+impl DisplayMode {
+    pub fn is_long(self) -> bool {
+        matches!(self, DisplayMode::Long | DisplayMode::Tree { long: true })
+    }
 }
 
 impl App {
     pub const DEFAULT: Self = Self {
         print_inode: false,
+        print_context: false,
+        print_capabilities: false,
         block_size_is_kilobytes: false,
+        block_size: BlockSize::new(1024),
+        file_block_size: BlockSize::BYTES,
+        block_size_arg: None,
         replace_unprintable_bytes: false,
+        quoting_style_arg: None,
         reverse_sorting: false,
-        grid_sort_direction: SortDirection::Horizontal,
+        grid_sort_direction: SortDirection::Vertical,
         display_size_in_blocks: false,
         display_mode: DisplayMode::Grid(0),
         show_all: ShowAll::No,
+        ignore_patterns: Vec::new(),
+        hide_patterns: Vec::new(),
+        git_ignore: None,
+        git_ignore_arg: None,
+        git_status: false,
+        git_repository: None,
+        xattrs: None,
         suffixes: Suffixes::None,
         follow_symlinks: FollowSymlinks::Never,
         recurse: false,
-        sort_field: Some(SortField::Name),
+        sort_keys: Some(Vec::new()),
+        group_directories_first: false,
+        tree: false,
+        tree_level: None,
+        ascii_guides: false,
         time_field: TimeField::Modified,
+        time_style: TimeStyle::LOCALE,
+        time_style_arg: None,
         list_directory_contents: true,
         convert_id_to_name: true,
         print_owner: true,
         print_group: true,
         color: Color::Auto,
+        hyperlink: Color::Never,
+        ls_colors: LsColors::EMPTY,
         out: OutputBuffer::to_fd(1),
         args: Args::None,
         uid_names: Vec::new(),
@@ -128,7 +244,10 @@ impl App {
         etc_passwd: &[],
         etc_group: &[],
         needs_details: false,
+        print_version: false,
+        statx_mask: 0,
         tzinfo: None,
+        environ: None,
     };
 
     #[inline(never)]
@@ -137,42 +256,69 @@ impl App {
 
         app.args = Args::new();
 
-        #[allow(non_snake_case)]
-        let mut switches_contains_H_or_L = false;
-        for arg in env::args().skip(1) {
-            if arg.get(0) == Some(b'-') {
-                for switch in arg.as_bytes().iter().copied().skip(1) {
-                    if switch == b'H' || switch == b'L' {
-                        switches_contains_H_or_L = true;
-                        break;
-                    }
-                }
-            }
-        }
-
-        let mut print_version = false;
         let mut args_valid = true;
 
-        for arg in env::args().skip(1) {
-            if arg.as_bytes() == b"--" {
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let arg = arg.as_bytes();
+            if arg == b"--" {
                 break;
-            } else if arg.as_bytes().starts_with(b"--") {
-                // Things like --color=always
-                match arg.as_bytes() {
-                    b"--color=never" => app.color = Color::Never,
-                    b"--color=auto" => app.color = Color::Auto,
-                    b"--color=always" => app.color = Color::Always,
-                    b"--version" => print_version = true,
-                    _ => {
+            } else if let Some(long) = arg.strip_prefix(b"--") {
+                // Things like --color=always or --sort size
+                let (name, value) = match long.iter().position(|b| *b == b'=') {
+                    Some(i) => (&long[..i], Some(&long[i + 1..])),
+                    None => (long, None),
+                };
+                let spec = match find_long_option(name) {
+                    Ok(spec) => spec,
+                    Err(LongOptionError::Unrecognized) => {
                         error!("unrecognized option \'", arg, "\'\n");
                         args_valid = false;
+                        continue;
+                    }
+                    Err(LongOptionError::Ambiguous) => {
+                        error!("option \'", arg, "\' is ambiguous\n");
+                        args_valid = false;
+                        continue;
                     }
+                };
+                let value = match (spec.arg, value) {
+                    (OptArg::None, Some(_)) => {
+                        error!("option \'--", spec.long, "\' doesn't allow an argument\n");
+                        args_valid = false;
+                        continue;
+                    }
+                    (OptArg::Required, None) => args.next().map(|a| a.as_bytes()),
+                    (_, value) => value,
+                };
+                if spec.arg == OptArg::Required && value.is_none() {
+                    error!("option \'--", spec.long, "\' requires an argument\n");
+                    args_valid = false;
+                } else if app.apply_option(spec, value).is_err() {
+                    args_valid = false;
                 }
-            } else if arg.get(0) == Some(b'-') {
+            } else if arg.first() == Some(&b'-') {
                 // Things like -R
-                for switch in arg.as_bytes().iter().copied().skip(1) {
-                    if app.handle_switch(switches_contains_H_or_L, switch).is_err() {
+                for (i, switch) in arg.iter().copied().enumerate().skip(1) {
+                    let Some(spec) = OPTIONS.iter().find(|o| o.short == Some(switch)) else {
+                        error!("invalid option \'", switch, "\'\n");
+                        args_valid = false;
+                        continue;
+                    };
+                    // A switch that takes a value uses the rest of this argument or the next one
+                    let value = match spec.arg {
+                        OptArg::Required if i + 1 < arg.len() => Some(&arg[i + 1..]),
+                        OptArg::Required => args.next().map(|a| a.as_bytes()),
+                        _ => None,
+                    };
+                    if spec.arg == OptArg::Required && value.is_none() {
+                        error!("option requires an argument -- \'", switch, "\'\n");
                         args_valid = false;
+                    } else if app.apply_option(spec, value).is_err() {
+                        args_valid = false;
+                    }
+                    if spec.arg == OptArg::Required {
+                        break;
                     }
                 }
             }
@@ -182,13 +328,21 @@ impl App {
             exit(1);
         }
 
-        if print_version {
+        // Only once we know all the arguments are valid
+        if app.print_version {
             app.out
                 .write(concat!("fls ", env!("CARGO_PKG_VERSION"), "\n").as_bytes())
                 .flush();
             exit(0);
         }
 
+        if let Some(block_size) = app.block_size_arg {
+            app.block_size = block_size;
+            app.file_block_size = block_size;
+        } else {
+            app.init_block_size();
+        }
+
         let terminal_width = winsize().ok().map(|d| d.ws_col as usize);
 
         match (terminal_width, app.display_mode) {
@@ -196,142 +350,405 @@ impl App {
             (None, DisplayMode::Grid(_)) => app.display_mode = DisplayMode::SingleColumn,
             _ => {}
         }
+        if app.tree && !matches!(app.display_mode, DisplayMode::Json { .. }) {
+            app.display_mode = DisplayMode::Tree {
+                long: app.display_mode == DisplayMode::Long,
+            };
+            app.ascii_guides = !app.locale_is_utf8();
+        }
 
-        if terminal_width.is_none() && app.color == Color::Auto {
+        let json = matches!(app.display_mode, DisplayMode::Json { .. });
+        if (terminal_width.is_none() && app.color == Color::Auto) || json {
             app.color = Color::Never;
         }
         if app.color == Color::Never {
             app.out.color = false;
+        } else if let Some(spec) = app.getenv(b"LS_COLORS") {
+            app.ls_colors = LsColors::parse(spec);
         }
+        app.out.replace_unprintable_bytes = app.replace_unprintable_bytes;
+        if !json
+            && (app.hyperlink == Color::Always
+                || (app.hyperlink == Color::Auto && terminal_width.is_some()))
+        {
+            app.out.hyperlinks = Hyperlinks::new();
+        }
+        // --git needs the ignore rules to tell ignored entries apart from untracked ones
+        let git_ignore_mode = app
+            .git_ignore_arg
+            .or(app.git_status.then_some(GitIgnoreMode::Show));
+        if let Some(mode) = git_ignore_mode {
+            let home = app.getenv(b"HOME");
+            let config_home = app.getenv(b"XDG_CONFIG_HOME");
+            app.git_ignore = Some(GitIgnore::new(mode, home, config_home));
+        }
+        // GNU ls warns about an invalid QUOTING_STYLE, we just ignore it
+        let env_quoting_style = app
+            .getenv(b"QUOTING_STYLE")
+            .and_then(|value| QUOTING_WORDS.iter().find(|w| w.0 == value));
+        // Unlike GNU ls, names aren't quoted on a terminal unless asked for, which also keeps
+        // measuring plain ASCII names cheap
+        app.out.quoting_style = if let Some(style) = app.quoting_style_arg {
+            style
+        } else if let Some(style) = env_quoting_style {
+            style.1
+        } else {
+            QuotingStyle::Literal
+        };
 
-        if app.display_mode == DisplayMode::Long {
+        if app.display_mode.is_long() || json {
             Self::init_id_map(
                 &b"/etc/passwd\0"[..],
                 &mut app.etc_passwd,
                 &mut app.uid_names,
             )?;
             Self::init_id_map(&b"/etc/group\0"[..], &mut app.etc_group, &mut app.gid_names)?;
-            let zi = crate::utils::fs_read(CStr::from_bytes(&b"/etc/localtime\0"[..])).unwrap();
-            app.tzinfo = Some(crate::time::Tzinfo::new(&zi));
         }
+        if app.display_mode.is_long() {
+            let tzinfo = crate::time::Tzinfo::load(app.getenv(b"TZ"));
+            app.tzinfo = Some(alloc::boxed::Box::leak(alloc::boxed::Box::new(tzinfo)));
+
+            if let Some(style) = app.time_style_arg {
+                app.time_style = style;
+            } else if let Some(style) = app.getenv(b"TIME_STYLE") {
+                // GNU ls exits if TIME_STYLE is invalid, we just fall back to the default
+                app.time_style = parse_time_style(style).unwrap_or(TimeStyle::LOCALE);
+            }
+        }
+
+        app.needs_details = app.display_mode.is_long()
+            || json
+            || app
+                .sort_keys
+                .iter()
+                .flatten()
+                .any(|key| matches!(key, SortField::Time | SortField::Size))
+            || app.display_size_in_blocks
+            || app.print_context
+            // The git index is compared against stat data
+            || app.git_status;
 
-        app.needs_details = app.display_mode == DisplayMode::Long
-            || app.sort_field == Some(SortField::Time)
-            || app.sort_field == Some(SortField::Size)
-            || app.display_size_in_blocks;
+        let mut mask = libc::STATX_TYPE | libc::STATX_MODE;
+        if app.display_mode.is_long() || json {
+            mask |= libc::STATX_NLINK
+                | libc::STATX_UID
+                | libc::STATX_GID
+                | libc::STATX_SIZE
+                | libc::STATX_BLOCKS
+                | libc::STATX_INO
+                | app.time_field.statx_mask();
+        }
+        if json {
+            mask |= libc::STATX_ATIME | libc::STATX_MTIME | libc::STATX_CTIME;
+        }
+        for key in app.sort_keys.iter().flatten() {
+            match key {
+                SortField::Size => mask |= libc::STATX_SIZE,
+                SortField::Time => mask |= app.time_field.statx_mask(),
+                _ => {}
+            }
+        }
+        if app.display_size_in_blocks {
+            mask |= libc::STATX_BLOCKS;
+        }
+        if app.git_status {
+            mask |= libc::STATX_SIZE | libc::STATX_INO | libc::STATX_MTIME;
+        }
+        app.statx_mask = mask;
 
         Ok(())
     }
 
-    #[allow(non_snake_case)]
-    fn handle_switch(&mut self, switches_contains_H_or_L: bool, switch: u8) -> Result<(), ()> {
+    fn apply_option(&mut self, spec: &OptSpec, arg: Option<&'static [u8]>) -> Result<(), ()> {
         let app = self;
-        match switch {
-            b'A' => {
+        // Only options with an optional argument look at whether there was one. --color= is not
+        // the same as --color.
+        let value = arg.unwrap_or_default();
+        match spec.opt {
+            Opt::AlmostAll => {
                 app.show_all = ShowAll::Almost;
             }
-            b'C' => {
+            Opt::IgnoreBackups => {
+                app.ignore_patterns.extend([&b"*~"[..], &b".*~"[..]]);
+            }
+            Opt::Vertical => {
                 app.display_mode = DisplayMode::Grid(0);
                 app.grid_sort_direction = SortDirection::Vertical;
             }
-            b'F' => {
-                if !switches_contains_H_or_L {
-                    app.follow_symlinks = FollowSymlinks::Never;
-                }
+            Opt::Classify => {
                 app.suffixes = Suffixes::All;
             }
-            b'H' => {
+            Opt::DereferenceCommandLine => {
                 app.follow_symlinks = FollowSymlinks::WhenExplicit;
             }
-            b'L' => {
+            Opt::Ignore => {
+                app.ignore_patterns.push(value);
+            }
+            Opt::Dereference => {
                 app.follow_symlinks = FollowSymlinks::Always;
             }
-            b'R' => {
+            Opt::Literal => {
+                app.quoting_style_arg = Some(QuotingStyle::Literal);
+            }
+            Opt::QuoteName => {
+                app.quoting_style_arg = Some(QuotingStyle::C);
+            }
+            Opt::Recursive => {
                 app.recurse = true;
             }
-            b'S' => {
-                app.sort_field = Some(SortField::Size);
+            Opt::SortBySize => {
+                app.sort_keys = Some(alloc::vec![SortField::Size]);
+            }
+            Opt::SortByExtension => {
+                app.sort_keys = Some(alloc::vec![SortField::Extension]);
             }
-            b'a' => {
+            Opt::All => {
                 app.show_all = ShowAll::Yes;
             }
-            b'c' => {
+            Opt::Escape => {
+                app.quoting_style_arg = Some(QuotingStyle::Escape);
+            }
+            Opt::StatusChangeTime => {
                 app.time_field = TimeField::StatusChanged;
-                app.sort_field = Some(SortField::Time);
+                app.sort_keys = Some(alloc::vec![SortField::Time]);
             }
-            b'd' => {
-                if !switches_contains_H_or_L {
-                    app.follow_symlinks = FollowSymlinks::Never;
-                }
+            Opt::Directory => {
                 app.list_directory_contents = false;
             }
-            b'f' => {
-                app.sort_field = None;
+            // Like -f, but without turning on -a
+            Opt::DirectoryOrder => {
+                app.sort_keys = None;
+            }
+            Opt::Unsorted => {
+                app.sort_keys = None;
                 app.show_all = ShowAll::Yes;
             }
-            b'g' => {
+            Opt::LongWithoutOwner => {
                 app.display_mode = DisplayMode::Long;
                 app.print_owner = false;
             }
-            b'i' => {
+            Opt::Inode => {
                 app.print_inode = true;
             }
-            b'k' => {
+            Opt::Context => {
+                app.print_context = true;
+            }
+            Opt::Kibibytes => {
                 app.block_size_is_kilobytes = true;
             }
-            b'l' => {
+            Opt::HumanReadable => {
+                app.block_size_arg = Some(BlockSize::Human { base: 1024 });
+            }
+            Opt::Si => {
+                app.block_size_arg = Some(BlockSize::Human { base: 1000 });
+            }
+            Opt::Long => {
                 app.display_mode = DisplayMode::Long;
             }
-            b'm' => {
+            Opt::Commas => {
                 app.display_mode = DisplayMode::Stream;
             }
-            b'n' => {
+            Opt::NumericIds => {
                 app.display_mode = DisplayMode::Long;
                 app.convert_id_to_name = false;
             }
-            b'o' => {
+            Opt::LongWithoutGroup => {
                 app.display_mode = DisplayMode::Long;
                 app.print_group = false;
             }
-            b'p' => {
+            Opt::SlashDirectories => {
                 app.suffixes = Suffixes::Directories;
             }
-            b'q' => {
+            Opt::HideControlChars => {
                 app.replace_unprintable_bytes = true;
-                unimplemented!("-q is not yet implemented");
             }
-            b'r' => {
+            Opt::Reverse => {
                 app.reverse_sorting = true;
             }
-            b's' => {
+            Opt::Size => {
                 app.display_size_in_blocks = true;
             }
-            b't' => {
+            Opt::ModificationTime => {
                 app.time_field = TimeField::Modified;
-                app.sort_field = Some(SortField::Time);
+                app.sort_keys = Some(alloc::vec![SortField::Time]);
             }
-            b'u' => {
+            Opt::AccessTime => {
                 app.time_field = TimeField::Accessed;
-                app.sort_field = Some(SortField::Time);
+                app.sort_keys = Some(alloc::vec![SortField::Time]);
             }
-            b'x' => {
+            Opt::Across => {
+                app.display_mode = DisplayMode::Grid(0);
                 app.grid_sort_direction = SortDirection::Horizontal;
-                unimplemented!("-x is not yet implemented");
             }
-            b'1' => match app.display_mode {
+            Opt::OnePerLine => match app.display_mode {
                 DisplayMode::Long => {}
                 _ => app.display_mode = DisplayMode::SingleColumn,
             },
-            s => {
-                error!("invalid option \'", s, "\'\n");
-                return Err(());
+            Opt::Sort => {
+                // Several keys can be given, like --sort=extension,size
+                let mut keys = Some(Vec::new());
+                for word in value.split(|b| *b == b',') {
+                    match parse_word(spec, word, SORT_WORDS)? {
+                        Some(key) => keys.iter_mut().for_each(|keys| keys.push(key)),
+                        None => keys = None,
+                    }
+                }
+                app.sort_keys = keys;
+            }
+            Opt::Time => {
+                app.time_field = parse_word(spec, value, TIME_WORDS)?;
+            }
+            Opt::Format => {
+                let (mode, direction) = parse_word(spec, value, FORMAT_WORDS)?;
+                app.display_mode = mode;
+                app.grid_sort_direction = direction;
+            }
+            Opt::Color => {
+                app.color = match arg {
+                    None => Color::Always,
+                    Some(value) => parse_word(spec, value, COLOR_WORDS)?,
+                };
+            }
+            Opt::TimeStyle => {
+                app.time_style_arg = Some(if let Some(format) = value.strip_prefix(b"+") {
+                    TimeStyle::custom(format)
+                } else if let Some(style) = value.strip_prefix(b"posix-") {
+                    // posix-STYLE only applies STYLE outside the POSIX locale, and that's the
+                    // only locale we have
+                    parse_word(spec, style, TIME_STYLE_WORDS)?;
+                    TimeStyle::LOCALE
+                } else {
+                    parse_word(spec, value, TIME_STYLE_WORDS)?
+                });
+            }
+            Opt::FullTime => {
+                app.display_mode = DisplayMode::Long;
+                app.time_style_arg = Some(TimeStyle::FULL_ISO);
+            }
+            Opt::BlockSize => {
+                app.block_size_arg = BlockSize::parse(value);
+                if app.block_size_arg.is_none() {
+                    error!("invalid --block-size argument \'", value, "\'\n");
+                    return Err(());
+                }
+            }
+            Opt::QuotingStyle => {
+                app.quoting_style_arg = Some(parse_word(spec, value, QUOTING_WORDS)?);
+            }
+            Opt::Hyperlink => {
+                app.hyperlink = match arg {
+                    None => Color::Always,
+                    Some(value) => parse_word(spec, value, COLOR_WORDS)?,
+                };
+            }
+            Opt::Hide => {
+                app.hide_patterns.push(value);
+            }
+            Opt::GroupDirectoriesFirst => {
+                app.group_directories_first = true;
+            }
+            Opt::GitIgnore => {
+                app.git_ignore_arg = Some(match arg {
+                    None => GitIgnoreMode::Hide,
+                    Some(value) => parse_word(spec, value, GIT_IGNORE_WORDS)?,
+                });
+            }
+            Opt::Git => {
+                app.git_status = true;
+            }
+            Opt::Capabilities => {
+                app.print_capabilities = true;
+            }
+            Opt::Xattrs => {
+                app.xattrs = Some(match arg {
+                    None => XattrListing::Names,
+                    Some(value) => parse_word(spec, value, XATTRS_WORDS)?,
+                });
+            }
+            Opt::Tree => {
+                app.tree = true;
+            }
+            Opt::Level => {
+                let valid = !value.is_empty()
+                    && value.len() < 10
+                    && value.iter().all(u8::is_ascii_digit)
+                    && crate::utils::atoi(value) > 0;
+                if !valid {
+                    error!("invalid level \'", value, "\'\n");
+                    return Err(());
+                }
+                app.tree_level = Some(crate::utils::atoi(value) as usize);
+            }
+            Opt::Version => {
+                app.print_version = true;
             }
         }
         Ok(())
     }
 
-    pub fn convert_to_localtime(&self, time: i64) -> crate::time::LocalTime {
-        self.tzinfo.as_ref().unwrap().convert_to_localtime(time)
+    /// Picks the block size from the environment the way GNU ls does. LS_BLOCK_SIZE and BLOCK_SIZE
+    /// also apply to file sizes, BLOCKSIZE and POSIXLY_CORRECT only to block counts.
+    fn init_block_size(&mut self) {
+        let ls_block_size = self.getenv(b"LS_BLOCK_SIZE");
+        let block_size = ls_block_size.or_else(|| self.getenv(b"BLOCK_SIZE"));
+        if let Some(size) = block_size.and_then(BlockSize::parse) {
+            self.block_size = size;
+            self.file_block_size = size;
+        } else if let Some(size) = self.getenv(b"BLOCKSIZE").and_then(BlockSize::parse) {
+            self.block_size = size;
+        } else if self.getenv(b"POSIXLY_CORRECT").is_some() {
+            self.block_size = BlockSize::new(512);
+        }
+        if self.block_size_is_kilobytes {
+            self.block_size = BlockSize::new(1024);
+        }
+    }
+
+    /// Whether -I, -B or --hide exclude this name from directory listings
+    pub fn ignores(&self, name: &[u8]) -> bool {
+        let hidden = self.show_all == ShowAll::No
+            && self
+                .hide_patterns
+                .iter()
+                .any(|p| crate::glob::matches(p, name));
+        hidden
+            || self
+                .ignore_patterns
+                .iter()
+                .any(|p| crate::glob::matches(p, name))
+    }
+
+    /// Whether the character set of the locale is UTF-8, going by the same variables setlocale
+    /// looks at
+    fn locale_is_utf8(&mut self) -> bool {
+        let locale = [&b"LC_ALL"[..], b"LC_CTYPE", b"LANG"]
+            .iter()
+            .find_map(|var| self.getenv(var).filter(|value| !value.is_empty()));
+        locale.is_some_and(|locale| {
+            locale.windows(5).any(|w| w.eq_ignore_ascii_case(b"utf-8"))
+                || locale.windows(4).any(|w| w.eq_ignore_ascii_case(b"utf8"))
+        })
+    }
+
+    /// Looks up an environment variable. veneer doesn't give us envp, so the environment is read
+    /// from /proc the first time it is needed.
+    pub fn getenv(&mut self, name: &[u8]) -> Option<&'static [u8]> {
+        let environ = *self.environ.get_or_insert_with(|| {
+            let contents = crate::utils::fs_read(CStr::from_bytes(b"/proc/self/environ\0"))
+                .unwrap_or_default();
+            alloc::boxed::Box::leak(contents.into_boxed_slice())
+        });
+        environ
+            .split(|b| *b == 0)
+            .find_map(|var| var.strip_prefix(name)?.strip_prefix(b"="))
+    }
+
+    pub fn convert_to_localtime(
+        &self,
+        time: i64,
+        nanoseconds: i64,
+    ) -> crate::time::LocalTime<'static> {
+        self.tzinfo.unwrap().convert_to_localtime(time, nanoseconds)
     }
 
     #[inline(never)]
@@ -378,8 +795,128 @@ impl App {
             .unwrap_or_default()
     }
 
-    pub fn convert_status(&self, status: libc::stat64) -> crate::Status {
+    /// Looks up the Status of an entry, following symlinks if -L was passed
+    pub fn stat(&self, dirfd: libc::c_int, name: CStr) -> Result<crate::Status, Error> {
+        self.stat_in(dirfd, name, &mut true)
+    }
+
+    /// Like stat, for one of several entries of a directory. xattrs is cleared once the
+    /// directory's filesystem turns out not to support extended attributes, so that the other
+    /// entries don't ask.
+    pub fn stat_in(
+        &self,
+        dirfd: libc::c_int,
+        name: CStr,
+        xattrs: &mut bool,
+    ) -> Result<crate::Status, Error> {
+        let flags = if self.follow_symlinks == FollowSymlinks::Always {
+            0
+        } else {
+            libc::AT_SYMLINK_NOFOLLOW
+        };
+        let mut status = None;
+        if !STATX_UNSUPPORTED.load(Ordering::Relaxed) {
+            match crate::syscalls::statx(dirfd, name, flags, self.statx_mask) {
+                Ok(s) => status = Some(self.convert_statx(&s)),
+                // statx was added in Linux 4.11
+                Err(Error(libc::ENOSYS)) => STATX_UNSUPPORTED.store(true, Ordering::Relaxed),
+                Err(err) => return Err(err),
+            }
+        }
+        let mut status = match status {
+            Some(status) => status,
+            None if flags == 0 => self.convert_status(fstatat(dirfd, name)?),
+            None => self.convert_status(lstatat(dirfd, name)?),
+        };
+        if *xattrs {
+            *xattrs = self.read_xattrs(dirfd, name, flags == 0, &mut status);
+        }
+        Ok(status)
+    }
+
+    /// Fills in what the options ask for from an entry's extended attributes. In long format we
+    /// list them anyway for the mode indicator, so the others are only read if they're there.
+    /// Returns false if the filesystem doesn't support them.
+    fn read_xattrs(
+        &self,
+        dirfd: libc::c_int,
+        name: CStr,
+        follow_symlinks: bool,
+        status: &mut crate::Status,
+    ) -> bool {
+        use crate::xattr;
+
+        let long = self.display_mode.is_long();
+        if !long && !self.print_context {
+            return true;
+        }
+        if long {
+            match xattr::list(dirfd, name, follow_symlinks) {
+                Ok(names) => status.xattrs = Some(names),
+                Err(Error(libc::ENOTSUP)) => return false,
+                Err(_) => return true,
+            }
+        }
+        let names = status.xattrs.as_deref();
+        let has = |attribute| names.is_none_or(|names| xattr::contains(names, attribute));
+        let context = (self.print_context && has(xattr::SELINUX))
+            .then(|| xattr::selinux_context(dirfd, name, follow_symlinks))
+            .flatten();
+        let capabilities = (self.print_capabilities && has(xattr::CAPABILITY))
+            .then(|| xattr::capabilities(dirfd, name, follow_symlinks))
+            .flatten();
+        status.mode_indicator = names.and_then(xattr::mode_indicator);
+        status.context = context;
+        status.capabilities = capabilities;
+        true
+    }
+
+    fn convert_statx(&self, status: &libc::statx) -> crate::Status {
         use TimeField::*;
+        let timestamp = |t: libc::statx_timestamp| (t.tv_sec, t.tv_nsec.into());
+        let time = match self.time_field {
+            Accessed => Some(timestamp(status.stx_atime)),
+            Modified => Some(timestamp(status.stx_mtime)),
+            StatusChanged => Some(timestamp(status.stx_ctime)),
+            Birth if status.stx_mask & libc::STATX_BTIME != 0 => Some(timestamp(status.stx_btime)),
+            Birth => None,
+        };
+        let (atime, atime_nsec) = timestamp(status.stx_atime);
+        let (mtime, mtime_nsec) = timestamp(status.stx_mtime);
+        let (ctime, ctime_nsec) = timestamp(status.stx_ctime);
+        crate::Status {
+            device: libc::makedev(status.stx_dev_major, status.stx_dev_minor),
+            links: status.stx_nlink.into(),
+            mode: status.stx_mode.into(),
+            size: status.stx_size as libc::off_t,
+            blocks: status.stx_blocks as libc::blkcnt64_t,
+            block_size: status.stx_blksize.into(),
+            uid: status.stx_uid,
+            gid: status.stx_gid,
+            inode: status.stx_ino,
+            rdev: libc::makedev(status.stx_rdev_major, status.stx_rdev_minor),
+            time,
+            mtime,
+            mtime_nsec,
+            atime,
+            atime_nsec,
+            ctime,
+            ctime_nsec,
+            context: None,
+            xattrs: None,
+            mode_indicator: None,
+            capabilities: None,
+        }
+    }
+
+    fn convert_status(&self, status: libc::stat64) -> crate::Status {
+        use TimeField::*;
+        let time = match self.time_field {
+            Accessed => Some((status.st_atime, status.st_atime_nsec)),
+            Modified => Some((status.st_mtime, status.st_mtime_nsec)),
+            StatusChanged => Some((status.st_ctime, status.st_ctime_nsec)),
+            Birth => None,
+        };
         crate::Status {
             device: status.st_dev,
             links: status.st_nlink,
@@ -390,12 +927,359 @@ impl App {
             uid: status.st_uid,
             gid: status.st_gid,
             inode: status.st_ino,
-            time: match self.time_field {
-                Accessed => status.st_atime,
-                Modified => status.st_mtime,
-                StatusChanged => status.st_ctime,
-            },
+            rdev: status.st_rdev,
+            time,
+            mtime: status.st_mtime,
+            mtime_nsec: status.st_mtime_nsec,
+            atime: status.st_atime,
+            atime_nsec: status.st_atime_nsec,
+            ctime: status.st_ctime,
+            ctime_nsec: status.st_ctime_nsec,
+            context: None,
+            xattrs: None,
+            mode_indicator: None,
+            capabilities: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Opt {
+    AlmostAll,
+    IgnoreBackups,
+    Vertical,
+    Classify,
+    DereferenceCommandLine,
+    Ignore,
+    Dereference,
+    Literal,
+    QuoteName,
+    Recursive,
+    SortBySize,
+    DirectoryOrder,
+    SortByExtension,
+    Context,
+    All,
+    Escape,
+    StatusChangeTime,
+    Directory,
+    Unsorted,
+    LongWithoutOwner,
+    Inode,
+    Kibibytes,
+    HumanReadable,
+    Si,
+    Long,
+    Commas,
+    NumericIds,
+    LongWithoutGroup,
+    SlashDirectories,
+    HideControlChars,
+    Reverse,
+    Size,
+    ModificationTime,
+    AccessTime,
+    Across,
+    OnePerLine,
+    Sort,
+    Time,
+    Format,
+    Color,
+    TimeStyle,
+    FullTime,
+    BlockSize,
+    QuotingStyle,
+    Hyperlink,
+    Hide,
+    GroupDirectoriesFirst,
+    GitIgnore,
+    Git,
+    Xattrs,
+    Capabilities,
+    Tree,
+    Level,
+    Version,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OptArg {
+    None,
+    Optional,
+    Required,
+}
+
+struct OptSpec {
+    short: Option<u8>,
+    long: &'static [u8],
+    arg: OptArg,
+    opt: Opt,
+}
+
+impl OptSpec {
+    const fn short(short: u8, opt: Opt) -> Self {
+        Self {
+            short: Some(short),
+            long: b"",
+            arg: OptArg::None,
+            opt,
+        }
+    }
+
+    const fn both(short: u8, long: &'static [u8], opt: Opt) -> Self {
+        Self {
+            short: Some(short),
+            long,
+            arg: OptArg::None,
+            opt,
+        }
+    }
+
+    const fn long(long: &'static [u8], arg: OptArg, opt: Opt) -> Self {
+        Self {
+            short: None,
+            long,
+            arg,
+            opt,
+        }
+    }
+}
+
+/// Every option we accept. Both the short switches and the long names are looked up here, so the
+/// two forms of an option always behave the same.
+static OPTIONS: &[OptSpec] = &[
+    OptSpec::both(b'A', b"almost-all", Opt::AlmostAll),
+    OptSpec::both(b'B', b"ignore-backups", Opt::IgnoreBackups),
+    OptSpec::short(b'C', Opt::Vertical),
+    OptSpec::both(b'F', b"classify", Opt::Classify),
+    OptSpec::both(
+        b'H',
+        b"dereference-command-line",
+        Opt::DereferenceCommandLine,
+    ),
+    OptSpec {
+        short: Some(b'I'),
+        long: b"ignore",
+        arg: OptArg::Required,
+        opt: Opt::Ignore,
+    },
+    OptSpec::both(b'L', b"dereference", Opt::Dereference),
+    OptSpec::both(b'N', b"literal", Opt::Literal),
+    OptSpec::both(b'Q', b"quote-name", Opt::QuoteName),
+    OptSpec::both(b'R', b"recursive", Opt::Recursive),
+    OptSpec::short(b'S', Opt::SortBySize),
+    OptSpec::short(b'U', Opt::DirectoryOrder),
+    OptSpec::short(b'X', Opt::SortByExtension),
+    OptSpec::both(b'Z', b"context", Opt::Context),
+    OptSpec::both(b'a', b"all", Opt::All),
+    OptSpec::both(b'b', b"escape", Opt::Escape),
+    OptSpec::short(b'c', Opt::StatusChangeTime),
+    OptSpec::both(b'd', b"directory", Opt::Directory),
+    OptSpec::short(b'f', Opt::Unsorted),
+    OptSpec::short(b'g', Opt::LongWithoutOwner),
+    OptSpec::both(b'i', b"inode", Opt::Inode),
+    OptSpec::both(b'k', b"kibibytes", Opt::Kibibytes),
+    OptSpec::both(b'h', b"human-readable", Opt::HumanReadable),
+    OptSpec::long(b"si", OptArg::None, Opt::Si),
+    OptSpec::short(b'l', Opt::Long),
+    OptSpec::short(b'm', Opt::Commas),
+    OptSpec::both(b'n', b"numeric-uid-gid", Opt::NumericIds),
+    OptSpec::short(b'o', Opt::LongWithoutGroup),
+    OptSpec::short(b'p', Opt::SlashDirectories),
+    OptSpec::both(b'q', b"hide-control-chars", Opt::HideControlChars),
+    OptSpec::both(b'r', b"reverse", Opt::Reverse),
+    OptSpec::both(b's', b"size", Opt::Size),
+    OptSpec::short(b't', Opt::ModificationTime),
+    OptSpec::short(b'u', Opt::AccessTime),
+    OptSpec::short(b'x', Opt::Across),
+    OptSpec::short(b'1', Opt::OnePerLine),
+    OptSpec::long(b"sort", OptArg::Required, Opt::Sort),
+    OptSpec::long(b"time", OptArg::Required, Opt::Time),
+    OptSpec::long(b"format", OptArg::Required, Opt::Format),
+    OptSpec::long(b"color", OptArg::Optional, Opt::Color),
+    OptSpec::long(b"time-style", OptArg::Required, Opt::TimeStyle),
+    OptSpec::long(b"full-time", OptArg::None, Opt::FullTime),
+    OptSpec::long(b"block-size", OptArg::Required, Opt::BlockSize),
+    OptSpec::long(b"quoting-style", OptArg::Required, Opt::QuotingStyle),
+    OptSpec::long(b"hyperlink", OptArg::Optional, Opt::Hyperlink),
+    OptSpec::long(b"hide", OptArg::Required, Opt::Hide),
+    OptSpec::long(
+        b"group-directories-first",
+        OptArg::None,
+        Opt::GroupDirectoriesFirst,
+    ),
+    OptSpec::long(b"git-ignore", OptArg::Optional, Opt::GitIgnore),
+    OptSpec::long(b"git", OptArg::None, Opt::Git),
+    OptSpec::long(b"xattrs", OptArg::Optional, Opt::Xattrs),
+    OptSpec::long(b"capabilities", OptArg::None, Opt::Capabilities),
+    OptSpec::long(b"tree", OptArg::None, Opt::Tree),
+    OptSpec::long(b"level", OptArg::Required, Opt::Level),
+    OptSpec::long(b"version", OptArg::None, Opt::Version),
+];
+
+static SORT_WORDS: &[(&[u8], Option<SortField>)] = &[
+    (b"none", None),
+    (b"name", Some(SortField::Name)),
+    (b"version", Some(SortField::Name)),
+    (b"size", Some(SortField::Size)),
+    (b"time", Some(SortField::Time)),
+    (b"extension", Some(SortField::Extension)),
+    (b"width", Some(SortField::Width)),
+    (b"inode", Some(SortField::Inode)),
+];
+
+static TIME_WORDS: &[(&[u8], TimeField)] = &[
+    (b"atime", TimeField::Accessed),
+    (b"access", TimeField::Accessed),
+    (b"use", TimeField::Accessed),
+    (b"ctime", TimeField::StatusChanged),
+    (b"status", TimeField::StatusChanged),
+    (b"mtime", TimeField::Modified),
+    (b"modification", TimeField::Modified),
+    (b"birth", TimeField::Birth),
+    (b"creation", TimeField::Birth),
+];
+
+static FORMAT_WORDS: &[(&[u8], (DisplayMode, SortDirection))] = &[
+    (b"across", (DisplayMode::Grid(0), SortDirection::Horizontal)),
+    (
+        b"horizontal",
+        (DisplayMode::Grid(0), SortDirection::Horizontal),
+    ),
+    (b"vertical", (DisplayMode::Grid(0), SortDirection::Vertical)),
+    (b"commas", (DisplayMode::Stream, SortDirection::Vertical)),
+    (b"long", (DisplayMode::Long, SortDirection::Vertical)),
+    (b"verbose", (DisplayMode::Long, SortDirection::Vertical)),
+    (
+        b"single-column",
+        (DisplayMode::SingleColumn, SortDirection::Vertical),
+    ),
+    (
+        b"json",
+        (DisplayMode::Json { lines: false }, SortDirection::Vertical),
+    ),
+    (
+        b"ndjson",
+        (DisplayMode::Json { lines: true }, SortDirection::Vertical),
+    ),
+];
+
+static QUOTING_WORDS: &[(&[u8], QuotingStyle)] = &[
+    (b"literal", QuotingStyle::Literal),
+    (b"shell", QuotingStyle::Shell),
+    (b"shell-always", QuotingStyle::ShellAlways),
+    (b"shell-escape", QuotingStyle::ShellEscape),
+    (b"shell-escape-always", QuotingStyle::ShellEscapeAlways),
+    (b"c", QuotingStyle::C),
+    (b"escape", QuotingStyle::Escape),
+    (b"locale", QuotingStyle::Locale),
+];
+
+static GIT_IGNORE_WORDS: &[(&[u8], GitIgnoreMode)] =
+    &[(b"hide", GitIgnoreMode::Hide), (b"dim", GitIgnoreMode::Dim)];
+
+static XATTRS_WORDS: &[(&[u8], XattrListing)] = &[
+    (b"names", XattrListing::Names),
+    (b"values", XattrListing::Values),
+];
+
+static COLOR_WORDS: &[(&[u8], Color)] = &[
+    (b"always", Color::Always),
+    (b"yes", Color::Always),
+    (b"force", Color::Always),
+    (b"never", Color::Never),
+    (b"no", Color::Never),
+    (b"none", Color::Never),
+    (b"auto", Color::Auto),
+    (b"tty", Color::Auto),
+    (b"if-tty", Color::Auto),
+];
+
+static TIME_STYLE_WORDS: &[(&[u8], TimeStyle)] = &[
+    (b"full-iso", TimeStyle::FULL_ISO),
+    (b"long-iso", TimeStyle::LONG_ISO),
+    (b"iso", TimeStyle::ISO),
+    (b"locale", TimeStyle::LOCALE),
+];
+
+/// Parses the TIME_STYLE environment variable, which accepts the same styles as --time-style
+fn parse_time_style(value: &'static [u8]) -> Option<TimeStyle> {
+    if let Some(format) = value.strip_prefix(b"+") {
+        return Some(TimeStyle::custom(format));
+    }
+    let style = value.strip_prefix(b"posix-").unwrap_or(value);
+    let parsed = TIME_STYLE_WORDS.iter().find(|w| w.0 == style)?.1;
+    Some(if style.len() < value.len() {
+        TimeStyle::LOCALE
+    } else {
+        parsed
+    })
+}
+
+enum LongOptionError {
+    Unrecognized,
+    Ambiguous,
+}
+
+/// Finds a long option by its full name or any unambiguous prefix of it, like getopt_long
+fn find_long_option(name: &[u8]) -> Result<&'static OptSpec, LongOptionError> {
+    if let Some(spec) = OPTIONS.iter().find(|o| o.long == name) {
+        return Ok(spec);
+    }
+    let mut candidates = OPTIONS
+        .iter()
+        .filter(|o| !name.is_empty() && o.long.starts_with(name));
+    let spec = candidates.next().ok_or(LongOptionError::Unrecognized)?;
+    if candidates.next().is_some() {
+        return Err(LongOptionError::Ambiguous);
+    }
+    Ok(spec)
+}
+
+/// Looks up the argument to an option like --sort, which may also be abbreviated as long as all
+/// the words it could be short for mean the same thing.
+fn parse_word<T: Copy + PartialEq>(
+    spec: &OptSpec,
+    value: &[u8],
+    words: &[(&[u8], T)],
+) -> Result<T, ()> {
+    if let Some(word) = words.iter().find(|w| w.0 == value) {
+        return Ok(word.1);
+    }
+    let mut candidates = words
+        .iter()
+        .filter(|w| !value.is_empty() && w.0.starts_with(value));
+    if let Some(first) = candidates.next() {
+        if candidates.all(|w| w.1 == first.1) {
+            return Ok(first.1);
         }
+        error!(
+            "ambiguous argument \'",
+            value, "\' for \'--", spec.long, "\'\n"
+        );
+    } else {
+        error!(
+            "invalid argument \'",
+            value, "\' for \'--", spec.long, "\'\n"
+        );
+    }
+    Err(())
+}
+
+/// Whether this option is followed by a separate argument that holds its value, like --sort size.
+/// Those values must not be mistaken for paths.
+fn takes_next_arg(arg: &[u8]) -> bool {
+    if let Some(long) = arg.strip_prefix(b"--") {
+        !long.contains(&b'=')
+            && matches!(find_long_option(long), Ok(spec) if spec.arg == OptArg::Required)
+    } else {
+        for (i, switch) in arg.iter().enumerate().skip(1) {
+            if let Some(spec) = OPTIONS.iter().find(|o| o.short == Some(*switch)) {
+                if spec.arg == OptArg::Required {
+                    return i + 1 == arg.len();
+                }
+            }
+        }
+        false
     }
 }
 
@@ -409,6 +1293,7 @@ pub enum Args {
 struct ArgsIter<I> {
     iter: I,
     hit_only_arg_marker: bool,
+    skip_next: bool,
     any_args: bool,
 }
 
@@ -416,13 +1301,19 @@ impl Args {
     fn new() -> Self {
         let mut args = 0usize;
         let mut hit_only_arg_marker = false;
+        let mut skip_next = false;
         for arg in veneer::env::args().skip(1) {
+            if core::mem::take(&mut skip_next) {
+                continue;
+            }
             if arg.as_bytes() == b"--" {
                 hit_only_arg_marker = true;
                 continue;
             }
             if hit_only_arg_marker || arg.as_bytes().first() != Some(&b'-') {
                 args += 1;
+            } else {
+                skip_next = takes_next_arg(arg.as_bytes());
             }
             if args > 1 {
                 return Args::Multiple;
@@ -440,6 +1331,7 @@ impl Args {
         ArgsIter {
             iter: veneer::env::args().skip(1),
             hit_only_arg_marker: false,
+            skip_next: false,
             any_args: false,
         }
     }
@@ -453,6 +1345,9 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         for arg in self.iter.by_ref() {
+            if core::mem::take(&mut self.skip_next) {
+                continue;
+            }
             if self.hit_only_arg_marker || arg.as_bytes().first() != Some(&b'-') {
                 self.any_args = true;
                 return Some(arg);
@@ -461,6 +1356,7 @@ where
                 self.hit_only_arg_marker = true;
                 continue;
             }
+            self.skip_next = takes_next_arg(arg.as_bytes());
         }
         if !self.any_args {
             self.any_args = true;
@@ -1,7 +1,7 @@
 use crate::{
     cli::{App, Color, FollowSymlinks},
     utils::memcmp,
-    Style,
+    xattr, Style,
 };
 use core::ffi::c_ulong;
 use veneer::{
@@ -30,10 +30,15 @@ pub trait DirEntryExt {
     fn style(&self, dir: &Directory, app: &App) -> (Style, Option<u8>);
     fn inode(&self) -> u64;
     fn blocks(&self) -> u64;
-    fn time(&self) -> libc::time_t;
+    /// The SELinux context for -Z, or ? like GNU ls if there isn't one
+    fn context(&self) -> &[u8];
+    /// Seconds then nanoseconds, so that entries sort by time the way they do in GNU ls. None if
+    /// the entry wasn't stat'd or is missing the birth time asked for.
+    fn time(&self) -> Option<(libc::time_t, i64)>;
+    fn is_directory(&self) -> bool;
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum EntryType {
     Directory,
     Executable,
@@ -42,6 +47,12 @@ pub enum EntryType {
     BrokenLink,
     Fifo,
     Socket,
+    BlockDevice,
+    CharDevice,
+    /// A regular file with file capabilities, which is only checked for with --color=always
+    Capability {
+        executable: bool,
+    },
     Other,
 }
 
@@ -62,7 +73,27 @@ impl EntryType {
             (BrokenLink, _) => (Some(RedBold), None),
             (Fifo, Suffixes::All) => (Some(YellowBold), Some(b'|')),
             (Socket, _) => (Some(MagentaBold), None),
-            (Fifo | Other, _) => (Some(YellowBold), None),
+            (Capability { executable: true }, Suffixes::All) => (Some(BlackOnRed), Some(b'*')),
+            (Capability { .. }, _) => (Some(BlackOnRed), None),
+            (Fifo | BlockDevice | CharDevice | Other, _) => (Some(YellowBold), None),
+        }
+    }
+
+    /// The LS_COLORS key for this type of entry
+    fn color_key(self) -> &'static [u8] {
+        use EntryType::*;
+        match self {
+            Directory => b"di",
+            Executable => b"ex",
+            Regular => b"fi",
+            Link => b"ln",
+            BrokenLink => b"or",
+            Fifo => b"pi",
+            Socket => b"so",
+            BlockDevice => b"bd",
+            CharDevice => b"cd",
+            Capability { .. } => b"ca",
+            Other => b"no",
         }
     }
 }
@@ -84,11 +115,22 @@ impl DirEntryExt for (DirEntry<'_>, Option<crate::Status>) {
         }
     }
 
-    fn time(&self) -> libc::time_t {
-        if let Some(st) = &self.1 {
-            st.time
-        } else {
-            0
+    fn context(&self) -> &[u8] {
+        self.1
+            .as_ref()
+            .and_then(|st| st.context.as_deref())
+            .unwrap_or(b"?")
+    }
+
+    fn time(&self) -> Option<(libc::time_t, i64)> {
+        self.1.as_ref().and_then(|st| st.time)
+    }
+
+    /// Prefers the Status, but falls back to d_type so that callers don't need to stat
+    fn is_directory(&self) -> bool {
+        match &self.1 {
+            Some(status) => status.mode & libc::S_IFMT == libc::S_IFDIR,
+            None => self.0.d_type == DType::DIR,
         }
     }
 
@@ -106,7 +148,8 @@ impl DirEntryExt for (DirEntry<'_>, Option<crate::Status>) {
                 DType::DIR => Directory,
                 DType::FIFO => Fifo,
                 DType::SOCK => Socket,
-                DType::CHR | DType::BLK => Other,
+                DType::CHR => CharDevice,
+                DType::BLK => BlockDevice,
                 DType::LNK => Link,
                 DType::REG | DType::UNKNOWN => Regular,
             }
@@ -115,41 +158,58 @@ impl DirEntryExt for (DirEntry<'_>, Option<crate::Status>) {
                 DType::DIR => Directory,
                 DType::FIFO => Fifo,
                 DType::SOCK => Socket,
-                DType::CHR | DType::BLK => Other,
+                DType::CHR => CharDevice,
+                DType::BLK => BlockDevice,
                 DType::REG => syscalls::faccessat(dir.raw_fd(), self.name(), libc::X_OK)
                     .map(|_| Executable)
                     .unwrap_or(Regular),
                 DType::LNK => syscalls::faccessat(dir.raw_fd(), self.name(), libc::F_OK)
                     .map(|_| Link)
                     .unwrap_or(BrokenLink),
-                DType::UNKNOWN => if app.follow_symlinks == FollowSymlinks::Always {
-                    syscalls::fstatat(dir.raw_fd(), self.0.name)
-                } else {
-                    syscalls::lstatat(dir.raw_fd(), self.0.name)
-                }
-                .map(|status| {
-                    let status = app.convert_status(status);
-                    let entry_type = status.mode & libc::S_IFMT;
-                    if entry_type == libc::S_IFDIR {
-                        Directory
-                    } else if entry_type == libc::S_IFIFO {
-                        Fifo
-                    } else if entry_type == libc::S_IFLNK {
-                        if app.color == Color::Always
-                            && syscalls::faccessat(dir.raw_fd(), self.0.name, libc::F_OK).is_err()
-                        {
-                            BrokenLink
+                DType::UNKNOWN => app
+                    .stat(dir.raw_fd(), self.0.name)
+                    .map(|status| {
+                        let entry_type = status.mode & libc::S_IFMT;
+                        if entry_type == libc::S_IFDIR {
+                            Directory
+                        } else if entry_type == libc::S_IFIFO {
+                            Fifo
+                        } else if entry_type == libc::S_IFLNK {
+                            if app.color == Color::Always
+                                && syscalls::faccessat(dir.raw_fd(), self.0.name, libc::F_OK)
+                                    .is_err()
+                            {
+                                BrokenLink
+                            } else {
+                                Link
+                            }
+                        } else if status.mode & libc::S_IXUSR > 0 {
+                            Executable
                         } else {
-                            Link
+                            Regular
                         }
-                    } else if status.mode & libc::S_IXUSR > 0 {
-                        Executable
-                    } else {
-                        Regular
-                    }
-                })
-                .unwrap_or(BrokenLink),
+                    })
+                    .unwrap_or(BrokenLink),
+            }
+        };
+
+        // Looking for capabilities costs a syscall for every file, so like GNU ls we don't when
+        // LS_COLORS turns them off, as the ca=00 that dircolors ships does
+        let follow_symlinks = app.follow_symlinks == FollowSymlinks::Always;
+        let entry_type = match entry_type {
+            Regular | Executable
+                if app.color == Color::Always
+                    && !app.ls_colors.disables(b"ca")
+                    && match self.1.as_ref().and_then(|s| s.xattrs.as_deref()) {
+                        Some(names) => xattr::contains(names, xattr::CAPABILITY),
+                        None => xattr::has_capabilities(dir.raw_fd(), self.name(), follow_symlinks),
+                    } =>
+            {
+                Capability {
+                    executable: entry_type == Executable,
+                }
             }
+            entry_type => entry_type,
         };
 
         let (style, suffix) = entry_type.style(app);
@@ -158,6 +218,18 @@ impl DirEntryExt for (DirEntry<'_>, Option<crate::Status>) {
             return (Style::Reset, suffix);
         }
 
+        if (app.git_ignore.as_ref())
+            .is_some_and(|g| g.dims(self.name().as_bytes(), self.is_directory()))
+        {
+            return (Style::Dim, suffix);
+        }
+
+        if !app.ls_colors.is_empty() {
+            if let Some(style) = ls_colors_style(app, entry_type, self) {
+                return (style, suffix);
+            }
+        }
+
         if let Some(style) = style {
             (style, suffix)
         } else {
@@ -183,6 +255,51 @@ pub fn extension_style(name: &[u8]) -> Style {
     }
 }
 
+/// Looks up the LS_COLORS style for an entry. Special permission bits and hard links are only
+/// taken into account when we already have a Status; we never stat just to color an entry.
+fn ls_colors_style(
+    app: &App,
+    entry_type: EntryType,
+    entry: &(DirEntry, Option<crate::Status>),
+) -> Option<Style> {
+    use EntryType::*;
+    let colors = &app.ls_colors;
+    if let Some(status) = &entry.1 {
+        let mode = status.mode;
+        let special = match entry_type {
+            Regular | Executable | Capability { .. } => {
+                if mode & libc::S_ISUID != 0 {
+                    colors.type_style(b"su")
+                } else if mode & libc::S_ISGID != 0 {
+                    colors.type_style(b"sg")
+                } else if entry_type != Regular {
+                    None
+                } else if status.links > 1 {
+                    colors.type_style(b"mh")
+                } else {
+                    None
+                }
+            }
+            Directory => match (mode & libc::S_ISVTX != 0, mode & libc::S_IWOTH != 0) {
+                (true, true) => colors.type_style(b"tw"),
+                (false, true) => colors.type_style(b"ow"),
+                (true, false) => colors.type_style(b"st"),
+                (false, false) => None,
+            },
+            _ => None,
+        };
+        if special.is_some() {
+            return special;
+        }
+    }
+    if entry_type == Regular {
+        if let Some(style) = colors.name_style(entry.name().as_bytes()) {
+            return Some(style);
+        }
+    }
+    colors.type_style(entry_type.color_key())
+}
+
 fn entry_type_from_status(status: &crate::Status) -> EntryType {
     use EntryType::*;
     let entry_type = status.mode & libc::S_IFMT;
@@ -194,6 +311,10 @@ fn entry_type_from_status(status: &crate::Status) -> EntryType {
         Socket
     } else if entry_type == libc::S_IFLNK {
         Link
+    } else if entry_type == libc::S_IFBLK {
+        BlockDevice
+    } else if entry_type == libc::S_IFCHR {
+        CharDevice
     } else if status.mode & libc::S_IXUSR > 0 {
         Executable
     } else if entry_type == libc::S_IFREG {
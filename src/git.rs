@@ -0,0 +1,953 @@
+use crate::{
+    cli::App,
+    directory::{DirEntry, DirEntryExt},
+    gitignore::{config_value, with_suffix, GitIgnore},
+    inflate::inflate,
+    output::{OutputBuffer, Writable},
+    syscalls,
+    utils::{fs_read, Mapping, SliceExt},
+    Status, Style,
+};
+use alloc::{collections::BTreeMap, rc::Rc, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    convert::TryInto,
+};
+use veneer::{
+    fs::{DType, Directory},
+    CStr,
+};
+
+/// How an entry has changed, in order of increasing importance so that a directory shows the
+/// most important change of anything inside it
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Change {
+    Unmodified,
+    Ignored,
+    Untracked,
+    Added,
+    Deleted,
+    Modified,
+    Conflicted,
+}
+
+/// Like the two letters from `git status --short`. The first is for changes that are staged but
+/// not committed, the second for changes in the working tree that aren't staged.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct GitStatus {
+    pub staged: Change,
+    pub unstaged: Change,
+}
+
+impl GitStatus {
+    const fn both(change: Change) -> Self {
+        Self {
+            staged: change,
+            unstaged: change,
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            staged: self.staged.max(other.staged),
+            unstaged: self.unstaged.max(other.unstaged),
+        }
+    }
+}
+
+impl Writable for GitStatus {
+    fn write(&self, out: &mut OutputBuffer) {
+        for (change, modified_style) in [
+            (self.staged, Style::GreenBold),
+            (self.unstaged, Style::RedBold),
+        ] {
+            let (style, letter) = match change {
+                Change::Unmodified => (Style::Gray, b'-'),
+                Change::Ignored => (Style::Gray, b'!'),
+                Change::Untracked => (Style::Red, b'?'),
+                Change::Added => (modified_style, b'A'),
+                Change::Deleted => (modified_style, b'D'),
+                Change::Modified => (modified_style, b'M'),
+                Change::Conflicted => (Style::RedBold, b'U'),
+            };
+            out.style(style).push(letter);
+        }
+    }
+}
+
+/// What we know about a repository from its index and the commit HEAD is at, without the help of
+/// git itself
+pub struct Repository {
+    /// The absolute path to the top of the working tree
+    root: Vec<u8>,
+    /// Every path in the index, one after another
+    paths: Vec<u8>,
+    /// Sorted by path, then by stage
+    entries: Vec<IndexEntry>,
+    /// Directories whose entry in the cache tree is still valid, with the id of the tree object
+    /// they'd be written as. Sorted by path.
+    valid_trees: Vec<(Vec<u8>, Vec<u8>)>,
+    /// How each entry differs from HEAD
+    staged: Vec<Change>,
+    /// Paths in HEAD that have been removed from the index, sorted
+    deleted: Vec<Vec<u8>>,
+    /// How each entry differs from the working tree, worked out when first needed
+    worktree: Vec<Option<Change>>,
+    /// Whether each directory we've looked through has untracked files somewhere inside it, by
+    /// its path with a slash on the end
+    untracked: BTreeMap<Vec<u8>, bool>,
+}
+
+struct IndexEntry {
+    /// The start and end of the path in Repository::paths
+    path: (usize, usize),
+    /// The id of the blob, only the first hash_len bytes of which are used
+    id: [u8; 32],
+    mtime: (u32, u32),
+    inode: u32,
+    mode: u32,
+    /// Truncated to 32 bits, just like inode
+    size: u32,
+    /// Nonzero for the sides of a merge conflict
+    stage: u16,
+    /// Set by assume-unchanged or skip-worktree, which both tell git not to look at the file
+    skip_worktree: bool,
+}
+
+impl Repository {
+    fn load(root: &[u8]) -> Option<Self> {
+        let git_dir = git_dir(root)?;
+        let common_dir = common_dir(&git_dir);
+        let config = fs_read(CStr::from_bytes(&with_suffix(&common_dir, b"/config")));
+        let hash_len = match config
+            .ok()
+            .as_deref()
+            .and_then(|config| config_value(config, b"extensions", b"objectformat"))
+        {
+            Some(b"sha256") => 32,
+            _ => 20,
+        };
+        // A new repository has no index until something is added
+        let index =
+            fs_read(CStr::from_bytes(&with_suffix(&git_dir, b"/index"))).unwrap_or_default();
+        let mut repository = Self {
+            root: root.to_vec(),
+            paths: Vec::new(),
+            entries: Vec::new(),
+            valid_trees: Vec::new(),
+            staged: Vec::new(),
+            deleted: Vec::new(),
+            worktree: Vec::new(),
+            untracked: BTreeMap::new(),
+        };
+        if !index.is_empty() {
+            repository.parse_index(&index, hash_len)?;
+        }
+        repository.worktree = alloc::vec![None; repository.entries.len()];
+
+        // Until we find them in HEAD, everything in the index is new
+        repository.staged = alloc::vec![Change::Added; repository.entries.len()];
+        let compared = match head(&git_dir, &common_dir) {
+            Head::Unborn => Some(()),
+            Head::Commit(id) => {
+                let objects = Objects::open(&common_dir, hash_len);
+                objects
+                    .commit_tree(&id)
+                    .and_then(|tree| repository.compare_tree(&objects, &tree, &mut Vec::new(), 0))
+            }
+            Head::Unknown => None,
+        };
+        // If we can't tell what's staged, it's better to show nothing than to guess
+        if compared.is_none() {
+            repository.staged.fill(Change::Unmodified);
+            repository.deleted.clear();
+        }
+        repository.deleted.sort_unstable();
+        Some(repository)
+    }
+
+    /// Reads versions 2 through 4 of the index format, as described in git's
+    /// Documentation/gitformat-index.txt
+    fn parse_index(&mut self, mut data: &[u8], hash_len: usize) -> Option<()> {
+        if data.read(4)? != b"DIRC" {
+            return None;
+        }
+        let version = data.read_u32_be()?;
+        if !(2..=4).contains(&version) {
+            return None;
+        }
+        let count = data.read_u32_be()?;
+        let mut previous: (usize, usize) = (0, 0);
+        for _ in 0..count {
+            let entry_start = data.len();
+            let _ctime = (data.read_u32_be()?, data.read_u32_be()?);
+            let mtime = (data.read_u32_be()?, data.read_u32_be()?);
+            let _device = data.read_u32_be()?;
+            let inode = data.read_u32_be()?;
+            let mode = data.read_u32_be()?;
+            let _uid = data.read_u32_be()?;
+            let _gid = data.read_u32_be()?;
+            let size = data.read_u32_be()?;
+            let mut id = [0u8; 32];
+            id[..hash_len].copy_from_slice(data.read(hash_len)?);
+            let flags = data.read_u16_be()?;
+            let extended_flags = if version >= 3 && flags & 0x4000 != 0 {
+                data.read_u16_be()?
+            } else {
+                0
+            };
+
+            let start = self.paths.len();
+            if version == 4 {
+                // Paths are stored as how much to remove from the end of the previous one, and
+                // what to add in its place
+                let keep = (previous.1 - previous.0).checked_sub(read_offset(&mut data)?)?;
+                self.paths.extend_from_within(previous.0..previous.0 + keep);
+                let len = data.iter().position(|b| *b == 0)?;
+                self.paths.extend_from_slice(&data.read(len + 1)?[..len]);
+            } else {
+                // Entries are padded with between 1 and 8 nulls to a multiple of 8 bytes
+                let len = data.iter().position(|b| *b == 0)?;
+                self.paths.extend_from_slice(&data[..len]);
+                let entry_len = entry_start - data.len() + len;
+                data.read((entry_len + 8) / 8 * 8 - (entry_start - data.len()))?;
+            }
+            previous = (start, self.paths.len());
+
+            self.entries.push(IndexEntry {
+                path: previous,
+                id,
+                mtime,
+                inode,
+                mode,
+                size,
+                stage: (flags >> 12) & 0x3,
+                skip_worktree: flags & 0x8000 != 0 || extended_flags & 0x4000 != 0,
+            });
+        }
+
+        // Extensions follow the entries, and the checksum of the whole file comes last
+        while data.len() > hash_len {
+            let signature = data.read(4)?;
+            let len = data.read_u32_be()? as usize;
+            let contents = data.read(len)?;
+            if signature == b"TREE" {
+                self.valid_trees = parse_cache_tree(contents, hash_len)?;
+            }
+        }
+        Some(())
+    }
+
+    fn path(&self, entry: &IndexEntry) -> &[u8] {
+        &self.paths[entry.path.0..entry.path.1]
+    }
+
+    /// The entries at path or, with a slash on the end, everything inside it
+    fn find(&self, path: &[u8]) -> core::ops::Range<usize> {
+        let first = self.entries.partition_point(|e| self.path(e) < path);
+        let matches = self.entries[first..]
+            .iter()
+            .take_while(|e| {
+                let entry_path = self.path(e);
+                entry_path == path || (path.ends_with(b"/") && entry_path.starts_with(path))
+            })
+            .count();
+        first..first + matches
+    }
+
+    /// The status of something in the directory at relative, or None if it's not in the index
+    fn status(
+        &mut self,
+        ignore: &GitIgnore,
+        relative: &[u8],
+        name: &[u8],
+        status: Option<&Status>,
+        is_directory: bool,
+    ) -> Option<GitStatus> {
+        let mut path = relative.to_vec();
+        if !path.is_empty() {
+            path.push(b'/');
+        }
+        path.extend_from_slice(name);
+
+        let entries = self.find(&path);
+        if !entries.is_empty() {
+            return entries
+                .map(|i| self.entry_status(i, status))
+                .reduce(GitStatus::merge);
+        }
+        if !is_directory {
+            return None;
+        }
+
+        // A directory shows the most important status of everything inside it
+        path.push(b'/');
+        let first = self.deleted.partition_point(|d| d[..] < path[..]);
+        let deleted = self
+            .deleted
+            .get(first)
+            .is_some_and(|d| d.starts_with(&path));
+        let mut status = self
+            .find(&path)
+            .map(|i| self.entry_status(i, None))
+            .chain(deleted.then_some(GitStatus {
+                staged: Change::Deleted,
+                unstaged: Change::Unmodified,
+            }))
+            .reduce(GitStatus::merge)?;
+        // Only the index knows about the files that are tracked, so we have to go looking for
+        // the ones that aren't
+        if status.unstaged < Change::Untracked && self.has_untracked(ignore, &mut path) {
+            status.unstaged = Change::Untracked;
+        }
+        Some(status)
+    }
+
+    /// Whether the directory at relative, which ends in a slash, has anything inside it that is
+    /// neither tracked nor ignored. Every directory looked through is remembered, so listing a
+    /// tree only goes through each part of it once.
+    fn has_untracked(&mut self, ignore: &GitIgnore, relative: &mut Vec<u8>) -> bool {
+        if let Some(found) = self.untracked.get(&relative[..]) {
+            return *found;
+        }
+        // The rules of the directories we pass through are only needed for this search
+        let mut ignore = ignore.clone();
+        let mut path = self.root.clone();
+        path.push(b'/');
+        let path = with_suffix(&path, &relative[..relative.len() - 1]);
+        self.search_untracked(&mut ignore, &path, relative)
+    }
+
+    fn search_untracked(
+        &mut self,
+        ignore: &mut GitIgnore,
+        path: &[u8],
+        relative: &mut Vec<u8>,
+    ) -> bool {
+        if let Some(found) = self.untracked.get(&relative[..]) {
+            return *found;
+        }
+        let Ok(dir) = Directory::open(CStr::from_bytes(path)) else {
+            return false;
+        };
+        let Ok(contents) = dir.read() else {
+            return false;
+        };
+        ignore.enter(&dir, path);
+        let found = contents.iter().any(|e| {
+            let name = e.name().as_bytes();
+            if name == b"." || name == b".." || name == b".git" {
+                return false;
+            }
+            let is_directory = match e.d_type() {
+                DType::DIR => true,
+                DType::UNKNOWN => syscalls::lstatat(dir.raw_fd(), e.name())
+                    .is_ok_and(|s| s.st_mode & libc::S_IFMT == libc::S_IFDIR),
+                _ => false,
+            };
+            if ignore.ignores(name, is_directory) {
+                return false;
+            }
+            let relative_len = relative.len();
+            relative.extend_from_slice(name);
+            // Submodules are in the index as a whole, so they're never looked inside
+            let found = if !self.find(relative).is_empty() {
+                false
+            } else if is_directory {
+                relative.push(b'/');
+                let mut child = path[..path.len() - 1].to_vec();
+                child.push(b'/');
+                self.search_untracked(ignore, &with_suffix(&child, name), relative)
+            } else {
+                true
+            };
+            relative.truncate(relative_len);
+            found
+        });
+        ignore.leave();
+        self.untracked.insert(relative.clone(), found);
+        found
+    }
+
+    fn entry_status(&mut self, i: usize, status: Option<&Status>) -> GitStatus {
+        let entry = &self.entries[i];
+        if entry.stage != 0 {
+            return GitStatus::both(Change::Conflicted);
+        }
+        let unstaged = match self.worktree[i] {
+            Some(change) => change,
+            None => {
+                let modified = match status {
+                    Some(status) => differs(
+                        entry,
+                        status.mode,
+                        status.size,
+                        status.inode,
+                        (status.mtime, status.mtime_nsec),
+                    ),
+                    None => {
+                        let mut path = self.root.clone();
+                        path.push(b'/');
+                        let path = with_suffix(&path, self.path(entry));
+                        match syscalls::lstatat(libc::AT_FDCWD, CStr::from_bytes(&path)) {
+                            Ok(status) => differs(
+                                entry,
+                                status.st_mode,
+                                status.st_size,
+                                status.st_ino,
+                                (status.st_mtime, status.st_mtime_nsec),
+                            ),
+                            Err(_) => {
+                                self.worktree[i] = Some(Change::Deleted);
+                                return GitStatus {
+                                    staged: self.staged[i],
+                                    unstaged: Change::Deleted,
+                                };
+                            }
+                        }
+                    }
+                };
+                let change = if modified {
+                    Change::Modified
+                } else {
+                    Change::Unmodified
+                };
+                self.worktree[i] = Some(change);
+                change
+            }
+        };
+        GitStatus {
+            staged: self.staged[i],
+            unstaged,
+        }
+    }
+
+    /// Walks the tree of the commit HEAD is at, marking each entry in the index that differs from
+    /// it, and collecting the paths that have been removed. dir is the path to the tree.
+    fn compare_tree(
+        &mut self,
+        objects: &Objects,
+        tree: &[u8],
+        dir: &mut Vec<u8>,
+        depth: usize,
+    ) -> Option<()> {
+        // The cache tree saves us from reading the directories that haven't been touched
+        let cached = self
+            .valid_trees
+            .binary_search_by(|(path, _)| path[..].cmp(dir));
+        if cached.is_ok_and(|i| self.valid_trees[i].1 == tree) {
+            let inside = if dir.is_empty() {
+                0..self.entries.len()
+            } else {
+                dir.push(b'/');
+                let inside = self.find(dir);
+                dir.pop();
+                inside
+            };
+            self.staged[inside].fill(Change::Unmodified);
+            return Some(());
+        }
+
+        if depth > 4096 {
+            return None;
+        }
+        let (kind, contents) = objects.read(tree)?;
+        if kind != TREE {
+            return None;
+        }
+        let mut contents = &contents[..];
+        while !contents.is_empty() {
+            let mode_len = contents.iter().position(|b| *b == b' ')?;
+            let mode = &contents.read(mode_len + 1)?[..mode_len];
+            let mode = mode.iter().try_fold(0u32, |mode, digit| match digit {
+                b'0'..=b'7' => Some(mode << 3 | u32::from(digit - b'0')),
+                _ => None,
+            })?;
+            let name_len = contents.iter().position(|b| *b == 0)?;
+            let name = &contents.read(name_len + 1)?[..name_len];
+            let id = contents.read(objects.hash_len)?;
+
+            let dir_len = dir.len();
+            if !dir.is_empty() {
+                dir.push(b'/');
+            }
+            dir.extend_from_slice(name);
+            if mode == 0o40000 {
+                self.compare_tree(objects, id, dir, depth + 1)?;
+            } else {
+                let entries = self.find(dir);
+                match self
+                    .entries
+                    .get(entries.start)
+                    .filter(|_| !entries.is_empty())
+                {
+                    Some(entry) if entry.stage == 0 => {
+                        self.staged[entries.start] =
+                            if entry.mode == mode && entry.id[..id.len()] == *id {
+                                Change::Unmodified
+                            } else {
+                                Change::Modified
+                            };
+                    }
+                    // Conflicts are shown as that and nothing else
+                    Some(_) => {}
+                    None => self.deleted.push(dir.clone()),
+                }
+            }
+            dir.truncate(dir_len);
+        }
+        Some(())
+    }
+}
+
+/// Whether the file has changed since the index recorded it
+fn differs(
+    entry: &IndexEntry,
+    mode: libc::mode_t,
+    size: libc::off_t,
+    inode: libc::ino_t,
+    mtime: (libc::time_t, i64),
+) -> bool {
+    if entry.skip_worktree {
+        return false;
+    }
+    let file_type = mode & libc::S_IFMT;
+    match entry.mode & libc::S_IFMT {
+        // Submodules are directories, and git doesn't look at their stat data either
+        0o160000 => return file_type != libc::S_IFDIR,
+        libc::S_IFLNK if file_type != libc::S_IFLNK => return true,
+        libc::S_IFREG
+            if file_type != libc::S_IFREG
+                || (mode & libc::S_IXUSR != 0) != (entry.mode & libc::S_IXUSR != 0) =>
+        {
+            return true
+        }
+        _ => {}
+    }
+    (mtime.0 as u32, mtime.1 as u32) != entry.mtime
+        || size as u32 != entry.size
+        || inode as u32 != entry.inode
+}
+
+/// The cache tree is a preorder walk of the directories in the index. Each one has its name, the
+/// number of entries it covers or -1 if it has been invalidated, how many subdirectories follow it
+/// and, if it's valid, the hash of its tree object.
+fn parse_cache_tree(mut data: &[u8], hash_len: usize) -> Option<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut valid = Vec::new();
+    let mut path = Vec::new();
+    // The length of the path to each directory we're in, and how many subdirectories of it are
+    // still to come
+    let mut stack: Vec<(usize, usize)> = Vec::new();
+    while !data.is_empty() {
+        let name_len = data.iter().position(|b| *b == 0)?;
+        let name = &data.read(name_len + 1)?[..name_len];
+        let count_len = data.iter().position(|b| *b == b' ')?;
+        let invalid = data.read(count_len + 1)?.starts_with(b"-");
+        let subtrees_len = data.iter().position(|b| *b == b'\n')?;
+        let subtrees = &data.read(subtrees_len + 1)?[..subtrees_len];
+        if !subtrees.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+
+        while stack.last().is_some_and(|(_, left)| *left == 0) {
+            stack.pop();
+        }
+        if let Some((len, left)) = stack.last_mut() {
+            *left -= 1;
+            path.truncate(*len);
+            if !path.is_empty() {
+                path.push(b'/');
+            }
+            path.extend_from_slice(name);
+        }
+        if !invalid {
+            valid.push((path.clone(), data.read(hash_len)?.to_vec()));
+        }
+        stack.push((path.len(), crate::utils::atoi(subtrees) as usize));
+    }
+    valid.sort_unstable();
+    Some(valid)
+}
+
+/// The variable-length integers in version 4 of the index, which packs use for the offsets of
+/// deltas too
+fn read_offset(data: &mut &[u8]) -> Option<usize> {
+    let mut byte = data.read(1)?[0];
+    let mut value = usize::from(byte & 0x7f);
+    while byte & 0x80 != 0 {
+        byte = data.read(1)?[0];
+        value = ((value + 1) << 7) | usize::from(byte & 0x7f);
+    }
+    Some(value)
+}
+
+const COMMIT: u8 = 1;
+const TREE: u8 = 2;
+const OFS_DELTA: u8 = 6;
+const REF_DELTA: u8 = 7;
+
+/// The objects in a repository, each either loose in a file of its own or in a pack, as described
+/// in git's Documentation/gitformat-pack.txt
+struct Objects {
+    /// The path to the objects directory
+    dir: Vec<u8>,
+    /// Each pack's index, then the pack itself
+    packs: Vec<(Mapping, Mapping)>,
+    hash_len: usize,
+    /// Objects that deltas have been applied to, by pack and offset. Trees are mostly deltas
+    /// against a few others near them, so without this reading a whole tree would inflate the
+    /// same chains over and over.
+    bases: RefCell<BTreeMap<(usize, usize), Rc<Object>>>,
+    /// How many bytes are in bases, which stops growing at BASE_CACHE_LIMIT
+    cached: Cell<usize>,
+}
+
+const BASE_CACHE_LIMIT: usize = 32 << 20;
+
+/// An object's type, then its contents
+type Object = (u8, Vec<u8>);
+
+impl Objects {
+    fn open(common_dir: &[u8], hash_len: usize) -> Self {
+        let mut dir = common_dir.to_vec();
+        dir.extend_from_slice(b"/objects");
+        let mut packs = Vec::new();
+        let pack_dir = with_suffix(&dir, b"/pack");
+        if let Ok(contents) = Directory::open(CStr::from_bytes(&pack_dir)).and_then(|d| d.read()) {
+            for entry in contents.iter() {
+                let Some(stem) = entry.name().as_bytes().strip_suffix(b".idx") else {
+                    continue;
+                };
+                let mut path = pack_dir[..pack_dir.len() - 1].to_vec();
+                path.push(b'/');
+                path.extend_from_slice(stem);
+                let index = Mapping::new(CStr::from_bytes(&with_suffix(&path, b".idx")));
+                let pack = Mapping::new(CStr::from_bytes(&with_suffix(&path, b".pack")));
+                if let (Ok(index), Ok(pack)) = (index, pack) {
+                    packs.push((index, pack));
+                }
+            }
+        }
+        Self {
+            dir,
+            packs,
+            hash_len,
+            bases: RefCell::new(BTreeMap::new()),
+            cached: Cell::new(0),
+        }
+    }
+
+    /// The id of the tree a commit points to
+    fn commit_tree(&self, id: &[u8]) -> Option<Vec<u8>> {
+        let (kind, contents) = self.read(id)?;
+        if kind != COMMIT {
+            return None;
+        }
+        let hex = contents.strip_prefix(b"tree ")?.get(..self.hash_len * 2)?;
+        from_hex(hex)
+    }
+
+    /// An object's type and contents
+    fn read(&self, id: &[u8]) -> Option<Object> {
+        self.read_at_depth(id, 0)
+    }
+
+    fn read_at_depth(&self, id: &[u8], depth: usize) -> Option<Object> {
+        match self.find(id) {
+            Some((pack, offset)) => self.read_packed(pack, offset, depth),
+            None => self.read_loose(id),
+        }
+    }
+
+    /// Which pack an object is in, and where
+    fn find(&self, id: &[u8]) -> Option<(usize, usize)> {
+        (self.packs.iter().enumerate())
+            .find_map(|(pack, (index, _))| Some((pack, self.find_in_pack(index, id)?)))
+    }
+
+    fn read_loose(&self, id: &[u8]) -> Option<Object> {
+        let mut path = self.dir.clone();
+        path.push(b'/');
+        for (i, b) in id.iter().enumerate() {
+            if i == 1 {
+                path.push(b'/');
+            }
+            path.extend_from_slice(&to_hex(*b));
+        }
+        path.push(0);
+        let compressed = fs_read(CStr::from_bytes(&path)).ok()?;
+        let contents = inflate(&compressed, compressed.len() * 2)?;
+        let header_len = contents.iter().position(|b| *b == 0)?;
+        let kind = match contents[..header_len].split(|b| *b == b' ').next()? {
+            b"commit" => COMMIT,
+            b"tree" => TREE,
+            _ => 0,
+        };
+        Some((kind, contents[header_len + 1..].to_vec()))
+    }
+
+    /// Looks an object up in a version 2 pack index, returning where it is in the pack
+    fn find_in_pack(&self, mut index: &[u8], id: &[u8]) -> Option<usize> {
+        if index.read(8)? != b"\xfftOc\0\0\0\x02" {
+            return None;
+        }
+        // How many objects there are with a first byte up to and including each value
+        let fanout = index.read(256 * 4)?;
+        let fanout = |i: usize| u32::from_be_bytes(fanout[i * 4..][..4].try_into().unwrap());
+        let count = fanout(255) as usize;
+        let first = match id[0] {
+            0 => 0,
+            b => fanout(usize::from(b) - 1) as usize,
+        };
+        let end = fanout(usize::from(id[0])) as usize;
+        let ids = index.read(count * self.hash_len)?;
+        let (mut low, mut high) = (first, end.min(count));
+        let i = loop {
+            if low >= high {
+                return None;
+            }
+            let mid = low + (high - low) / 2;
+            match ids[mid * self.hash_len..][..self.hash_len].cmp(id) {
+                core::cmp::Ordering::Less => low = mid + 1,
+                core::cmp::Ordering::Greater => high = mid,
+                core::cmp::Ordering::Equal => break mid,
+            }
+        };
+
+        // The CRCs come before the offsets, and offsets too big for 31 bits are in a table after
+        let mut offsets = index.get(count * 4..)?;
+        let offset = offsets.get(i * 4..)?.read_u32_be()?;
+        if offset & 0x8000_0000 == 0 {
+            return Some(offset as usize);
+        }
+        offsets = offsets.get(count * 4 + (offset & 0x7fff_ffff) as usize * 8..)?;
+        let high = offsets.read_u32_be()?;
+        let low = offsets.read_u32_be()?;
+        Some((u64::from(high) << 32 | u64::from(low)) as usize)
+    }
+
+    fn read_packed(&self, pack: usize, offset: usize, depth: usize) -> Option<Object> {
+        // git itself won't make chains of deltas any longer than this
+        if depth > 4096 {
+            return None;
+        }
+        let mut data = self.packs[pack].1.get(offset..)?;
+        // The type, then the size in little-endian groups of 7 bits, the first of which only has
+        // room for 4
+        let mut byte = data.read(1)?[0];
+        let kind = (byte >> 4) & 0x7;
+        let mut size = usize::from(byte & 0xf);
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            byte = data.read(1)?[0];
+            size |= usize::from(byte & 0x7f).checked_shl(shift)?;
+            shift += 7;
+        }
+        let base = match kind {
+            OFS_DELTA => {
+                let base = offset.checked_sub(read_offset(&mut data)?)?;
+                self.base(pack, base, depth + 1)?
+            }
+            REF_DELTA => {
+                let id = data.read(self.hash_len)?;
+                match self.find(id) {
+                    Some((pack, offset)) => self.base(pack, offset, depth + 1)?,
+                    None => Rc::new(self.read_loose(id)?),
+                }
+            }
+            _ => return Some((kind, inflate_exact(data, size)?)),
+        };
+        let (kind, base) = &*base;
+        Some((*kind, apply_delta(base, &inflate_exact(data, size)?)?))
+    }
+
+    /// Reads an object that a delta applies to, from the cache if we've seen it before
+    fn base(&self, pack: usize, offset: usize, depth: usize) -> Option<Rc<Object>> {
+        if let Some(base) = self.bases.borrow().get(&(pack, offset)) {
+            return Some(base.clone());
+        }
+        let base = Rc::new(self.read_packed(pack, offset, depth)?);
+        let cached = self.cached.get() + base.1.len();
+        if cached <= BASE_CACHE_LIMIT {
+            self.cached.set(cached);
+            self.bases.borrow_mut().insert((pack, offset), base.clone());
+        }
+        Some(base)
+    }
+}
+
+/// Inflates something from a pack, which says how big it is. A damaged pack could say anything,
+/// so no more is allocated up front than the pack could hold.
+fn inflate_exact(data: &[u8], size: usize) -> Option<Vec<u8>> {
+    inflate(data, size.min(data.len())).filter(|inflated| inflated.len() == size)
+}
+
+/// A delta is the sizes of its base and the result, then instructions to either copy part of the
+/// base or insert new data
+fn apply_delta(base: &[u8], mut delta: &[u8]) -> Option<Vec<u8>> {
+    fn read_size(delta: &mut &[u8]) -> Option<usize> {
+        let mut size = 0usize;
+        let mut shift = 0;
+        loop {
+            let byte = delta.read(1)?[0];
+            size |= usize::from(byte & 0x7f).checked_shl(shift)?;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Some(size);
+            }
+        }
+    }
+
+    if read_size(&mut delta)? != base.len() {
+        return None;
+    }
+    let size = read_size(&mut delta)?;
+    // Each byte of the delta makes at most 0x10000 bytes of output
+    let mut out = Vec::with_capacity(size.min(delta.len().saturating_mul(0x10000)));
+    while let Some(&[op]) = delta.read(1) {
+        if op & 0x80 != 0 {
+            // Which bytes of the offset and size are present is given by the low bits of op
+            let mut offset = 0usize;
+            let mut len = 0usize;
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    offset |= usize::from(delta.read(1)?[0]) << (8 * i);
+                }
+            }
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    len |= usize::from(delta.read(1)?[0]) << (8 * i);
+                }
+            }
+            if len == 0 {
+                len = 0x10000;
+            }
+            out.extend_from_slice(base.get(offset..offset.checked_add(len)?)?);
+        } else if op != 0 {
+            out.extend_from_slice(delta.read(usize::from(op))?);
+        } else {
+            return None;
+        }
+    }
+    (out.len() == size).then_some(out)
+}
+
+enum Head {
+    Commit(Vec<u8>),
+    /// A new repository, or an orphan branch, without any commits yet
+    Unborn,
+    Unknown,
+}
+
+/// Follows HEAD to the commit it's at, looking for branches first as files of their own and then
+/// in packed-refs
+fn head(git_dir: &[u8], common_dir: &[u8]) -> Head {
+    let Ok(mut contents) = fs_read(CStr::from_bytes(&with_suffix(git_dir, b"/HEAD"))) else {
+        return Head::Unknown;
+    };
+    let mut refs = common_dir.to_vec();
+    refs.push(b'/');
+    // A branch can point to another branch, but not for long
+    for _ in 0..5 {
+        let Some(name) = contents.strip_prefix(b"ref:") else {
+            return from_hex(contents.trim_ascii()).map_or(Head::Unknown, Head::Commit);
+        };
+        let name = name.trim_ascii();
+        match fs_read(CStr::from_bytes(&with_suffix(&refs, name))) {
+            Ok(next) => contents = next,
+            Err(_) => {
+                let packed = fs_read(CStr::from_bytes(&with_suffix(common_dir, b"/packed-refs")))
+                    .unwrap_or_default();
+                let id = packed
+                    .split(|b| *b == b'\n')
+                    .filter_map(|line| {
+                        let space = line.iter().position(|b| *b == b' ')?;
+                        Some((&line[..space], &line[space + 1..]))
+                    })
+                    .find(|(_, line_name)| *line_name == name)
+                    .and_then(|(id, _)| from_hex(id));
+                return id.map_or(Head::Unborn, Head::Commit);
+            }
+        }
+    }
+    Head::Unknown
+}
+
+fn from_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let digit = |d: u8| (d as char).to_digit(16).map(|d| d as u8);
+    hex.chunks(2)
+        .map(|pair| Some(digit(pair[0])? << 4 | digit(pair[1])?))
+        .collect()
+}
+
+fn to_hex(byte: u8) -> [u8; 2] {
+    let hex = b"0123456789abcdef";
+    [hex[usize::from(byte >> 4)], hex[usize::from(byte & 0xf)]]
+}
+
+/// Linked worktrees have a git directory of their own, but share the objects, branches and
+/// config of the main one
+fn common_dir(git_dir: &[u8]) -> Vec<u8> {
+    let Ok(contents) = fs_read(CStr::from_bytes(&with_suffix(git_dir, b"/commondir"))) else {
+        return git_dir.to_vec();
+    };
+    let target = contents.trim_ascii();
+    if target.starts_with(b"/") {
+        return target.to_vec();
+    }
+    let mut dir = git_dir.to_vec();
+    dir.push(b'/');
+    dir.extend_from_slice(target);
+    dir
+}
+
+/// Usually root/.git, but worktrees and submodules have a file there that points elsewhere
+fn git_dir(root: &[u8]) -> Option<Vec<u8>> {
+    let mut dir = root.to_vec();
+    dir.extend_from_slice(b"/.git");
+    let path = with_suffix(&dir, b"");
+    let status = syscalls::lstatat(libc::AT_FDCWD, CStr::from_bytes(&path)).ok()?;
+    if status.st_mode & libc::S_IFMT == libc::S_IFDIR {
+        return Some(dir);
+    }
+    let contents = fs_read(CStr::from_bytes(&path)).ok()?;
+    let target = contents.strip_prefix(b"gitdir:")?.trim_ascii();
+    if target.starts_with(b"/") {
+        return Some(target.to_vec());
+    }
+    let mut dir = root.to_vec();
+    dir.push(b'/');
+    dir.extend_from_slice(target);
+    Some(dir)
+}
+
+/// Makes sure the index is loaded for the repository of the directory about to be listed
+pub fn load_repository(app: &mut App) {
+    let Some((root, _)) = app.git_ignore.as_ref().and_then(|g| g.repository()) else {
+        return;
+    };
+    if app.git_repository.as_ref().is_some_and(|r| r.root == root) {
+        return;
+    }
+    app.git_repository = Repository::load(root);
+}
+
+/// The status of an entry of the directory being listed, or None when the directory isn't in a
+/// repository we could read, in which case there is no column at all
+pub fn status(app: &mut App, entry: &(DirEntry, Option<Status>)) -> Option<GitStatus> {
+    let ignore = app.git_ignore.as_ref()?;
+    let (root, relative) = ignore.repository()?;
+    let repository = app.git_repository.as_mut().filter(|r| r.root == root)?;
+    let name = entry.name().as_bytes();
+    let is_directory = entry.is_directory();
+    Some(
+        match repository.status(ignore, relative, name, entry.1.as_ref(), is_directory) {
+            Some(status) => status,
+            None if ignore.ignores(name, is_directory) => GitStatus::both(Change::Ignored),
+            None => GitStatus::both(Change::Untracked),
+        },
+    )
+}
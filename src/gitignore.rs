@@ -0,0 +1,315 @@
+use crate::{glob, output::push_path_components, syscalls, utils::fs_read_at};
+use alloc::vec::Vec;
+use veneer::{fs::Directory, CStr};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum GitIgnoreMode {
+    /// Without --git-ignore, --git still needs the rules to tell ignored entries from untracked
+    Show,
+    Hide,
+    Dim,
+}
+
+/// The rules from .gitignore, .ignore, .git/info/exclude and the global excludes file that apply
+/// to the directory being listed. Rules are added as we walk down into a directory and dropped
+/// again when we leave it.
+#[derive(Clone)]
+pub struct GitIgnore {
+    pub mode: GitIgnoreMode,
+    /// From lowest to highest precedence, so the last rule that matches decides
+    rules: Vec<Rule>,
+    /// How many rules at the start came from the global excludes file
+    global_rules: usize,
+    /// The absolute path of the directory being listed
+    dir: Vec<u8>,
+    /// The length of the path to the top of the repository dir is in, if it's in one
+    root: Option<usize>,
+    /// The lengths of rules and dir from before we entered each directory we're in
+    levels: Vec<(usize, usize)>,
+    /// How many levels deep we were when we entered a directory that is ignored itself. git
+    /// doesn't look inside those, so everything in them is ignored too.
+    ignored_from: Option<usize>,
+}
+
+#[derive(Clone)]
+struct Rule {
+    pattern: Vec<u8>,
+    /// The length of the absolute path of the directory the pattern is relative to
+    base: usize,
+    negated: bool,
+    directories_only: bool,
+    /// A pattern with a slash in it matches the path from base, otherwise it matches just the
+    /// name at any depth
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &[u8], base: usize) -> Option<Self> {
+        let mut line = line.strip_suffix(b"\r").unwrap_or(line);
+        // Trailing spaces are ignored unless escaped with a backslash
+        while let Some(trimmed) = line.strip_suffix(b" ") {
+            if trimmed.ends_with(b"\\") {
+                break;
+            }
+            line = trimmed;
+        }
+        if line.is_empty() || line[0] == b'#' {
+            return None;
+        }
+        let negated = line[0] == b'!';
+        if negated {
+            line = &line[1..];
+        }
+        let directories_only = line.len() > 1 && line.ends_with(b"/");
+        if directories_only {
+            line = &line[..line.len() - 1];
+        }
+        let anchored = line.contains(&b'/');
+        let pattern = line.strip_prefix(b"/").unwrap_or(line);
+        Some(Self {
+            pattern: pattern.to_vec(),
+            base,
+            negated,
+            directories_only,
+            anchored,
+        })
+    }
+}
+
+impl GitIgnore {
+    pub fn new(mode: GitIgnoreMode, home: Option<&[u8]>, config_home: Option<&[u8]>) -> Self {
+        let mut ignore = Self {
+            mode,
+            rules: Vec::new(),
+            global_rules: 0,
+            dir: Vec::new(),
+            root: None,
+            levels: Vec::new(),
+            ignored_from: None,
+        };
+        if let Some(path) = global_excludes_file(home, config_home) {
+            ignore.load(libc::AT_FDCWD, &path, 0);
+        }
+        ignore.global_rules = ignore.rules.len();
+        ignore
+    }
+
+    /// Picks up the ignore files in a directory before listing it. path is how we got there,
+    /// which for the first directory is used to find the repository it's in.
+    pub fn enter(&mut self, dir: &Directory, path: &[u8]) {
+        let path = path.strip_suffix(b"\0").unwrap_or(path);
+        self.levels.push((self.rules.len(), self.dir.len()));
+        if self.levels.len() == 1 {
+            self.enter_repository(path);
+        } else {
+            let name = path.rsplit(|b| *b == b'/').next().unwrap_or(path);
+            if self.ignored_from.is_none() && self.ignores(name, true) {
+                self.ignored_from = Some(self.levels.len());
+            }
+            push_path_components(&mut self.dir, name);
+        }
+        let base = self.dir.len();
+        self.load(dir.raw_fd(), b".gitignore\0", base);
+        // .ignore files are meant to override .gitignore
+        self.load(dir.raw_fd(), b".ignore\0", base);
+    }
+
+    pub fn leave(&mut self) {
+        if self.ignored_from == Some(self.levels.len()) {
+            self.ignored_from = None;
+        }
+        if let Some((rules, dir)) = self.levels.pop() {
+            self.rules.truncate(rules);
+            self.dir.truncate(dir);
+        }
+    }
+
+    /// The absolute path to the top of the repository and the path from there to the directory
+    /// being listed
+    pub fn repository(&self) -> Option<(&[u8], &[u8])> {
+        let root = self.root.filter(|_| !self.levels.is_empty())?;
+        let relative = self.dir.get(root + 1..).unwrap_or_default();
+        Some((&self.dir[..root], relative))
+    }
+
+    /// Whether this entry of the directory being listed should be left out
+    pub fn hides(&self, name: &[u8], is_directory: bool) -> bool {
+        self.mode == GitIgnoreMode::Hide && self.ignores(name, is_directory)
+    }
+
+    /// Whether this entry of the directory being listed should be printed dimmed
+    pub fn dims(&self, name: &[u8], is_directory: bool) -> bool {
+        self.mode == GitIgnoreMode::Dim && self.ignores(name, is_directory)
+    }
+
+    pub fn ignores(&self, name: &[u8], is_directory: bool) -> bool {
+        // Names given on the command line aren't in a directory we've entered
+        if self.levels.is_empty() {
+            return false;
+        }
+        if self.ignored_from.is_some() {
+            return true;
+        }
+        let mut path = Vec::with_capacity(self.dir.len() + 1 + name.len());
+        path.extend_from_slice(&self.dir);
+        path.push(b'/');
+        path.extend_from_slice(name);
+        self.matches(&path, is_directory)
+    }
+
+    /// Whether the last rule that matches the absolute path excludes it
+    fn matches(&self, path: &[u8], is_directory: bool) -> bool {
+        let name = path.rsplit(|b| *b == b'/').next().unwrap_or(path);
+        for rule in self.rules.iter().rev() {
+            if rule.directories_only && !is_directory {
+                continue;
+            }
+            let subject = if rule.anchored {
+                &path[rule.base + 1..]
+            } else {
+                name
+            };
+            if glob::matches_path(&rule.pattern, subject) {
+                return !rule.negated;
+            }
+        }
+        false
+    }
+
+    /// Finds the closest enclosing directory with a .git in it, then loads the rules between it
+    /// and the directory we're about to list. Outside of a repository, the directory listed is
+    /// treated as the top of one.
+    fn enter_repository(&mut self, path: &[u8]) {
+        self.rules.truncate(self.global_rules);
+        self.dir.clear();
+        self.ignored_from = None;
+        if !path.starts_with(b"/") {
+            let mut buf = [0u8; libc::PATH_MAX as usize];
+            if let Ok(cwd) = syscalls::getcwd(&mut buf) {
+                self.dir
+                    .extend_from_slice(cwd.strip_suffix(b"/").unwrap_or(cwd));
+            }
+        }
+        push_path_components(&mut self.dir, path);
+
+        let mut root = Some(self.dir.len());
+        while let Some(end) = root {
+            if exists(&with_suffix(&self.dir[..end], b"/.git")) {
+                break;
+            }
+            root = self.dir[..end].iter().rposition(|b| *b == b'/');
+        }
+        self.root = root;
+        let root = root.unwrap_or(self.dir.len());
+
+        // Patterns in the global and repository excludes files are relative to the top
+        for rule in &mut self.rules {
+            rule.base = root;
+        }
+        let exclude = with_suffix(&self.dir[..root], b"/.git/info/exclude");
+        self.load(libc::AT_FDCWD, &exclude, root);
+
+        let ancestors = core::iter::once(root)
+            .chain((root + 1..self.dir.len()).filter(|i| self.dir[*i] == b'/'));
+        for end in ancestors
+            .filter(|end| *end < self.dir.len())
+            .collect::<Vec<_>>()
+        {
+            for file in [&b"/.gitignore"[..], b"/.ignore"] {
+                let path = with_suffix(&self.dir[..end], file);
+                self.load(libc::AT_FDCWD, &path, end);
+            }
+            // With the rules that apply to it loaded, see if the next directory down is ignored
+            let next = (self.dir[end + 1..].iter().position(|b| *b == b'/'))
+                .map_or(self.dir.len(), |i| end + 1 + i);
+            if self.matches(&self.dir[..next], true) {
+                self.ignored_from = Some(self.levels.len());
+            }
+        }
+    }
+
+    fn load(&mut self, dirfd: libc::c_int, path: &[u8], base: usize) {
+        if let Ok(contents) = fs_read_at(dirfd, CStr::from_bytes(path)) {
+            self.rules.extend(
+                contents
+                    .split(|b| *b == b'\n')
+                    .filter_map(|line| Rule::parse(line, base)),
+            );
+        }
+    }
+}
+
+/// core.excludesFile from the user's git config, or git's default of $XDG_CONFIG_HOME/git/ignore
+fn global_excludes_file(home: Option<&[u8]>, config_home: Option<&[u8]>) -> Option<Vec<u8>> {
+    let config_home = match config_home.filter(|dir| !dir.is_empty()) {
+        Some(dir) => dir.to_vec(),
+        None => {
+            let mut dir = home?.to_vec();
+            dir.extend_from_slice(b"/.config");
+            dir
+        }
+    };
+    let mut configured = None;
+    // Later files take precedence, as they do for git
+    for config in [
+        with_suffix(&config_home, b"/git/config"),
+        with_suffix(home.unwrap_or_default(), b"/.gitconfig"),
+    ] {
+        if let Ok(contents) = fs_read_at(libc::AT_FDCWD, CStr::from_bytes(&config)) {
+            if let Some(value) = config_value(&contents, b"core", b"excludesfile") {
+                configured = Some(value.to_vec());
+            }
+        }
+    }
+    match configured {
+        Some(value) => match value.strip_prefix(b"~/") {
+            Some(rest) => {
+                let mut path = home?.to_vec();
+                path.push(b'/');
+                Some(with_suffix(&path, rest))
+            }
+            None => Some(with_suffix(&value, b"")),
+        },
+        None => Some(with_suffix(&config_home, b"/git/ignore")),
+    }
+}
+
+/// Finds the last value for a key in a git config file. Only the simple `key = value` and
+/// `key = "value"` forms are understood.
+pub fn config_value<'a>(contents: &'a [u8], section: &[u8], key: &[u8]) -> Option<&'a [u8]> {
+    let mut in_section = false;
+    let mut value = None;
+    for line in contents.split(|b| *b == b'\n') {
+        let line = line.trim_ascii();
+        if let Some(header) = line.strip_prefix(b"[") {
+            let name = header.split(|b| *b == b']').next().unwrap_or_default();
+            in_section = name.trim_ascii().eq_ignore_ascii_case(section);
+        } else if in_section {
+            let Some(equals) = line.iter().position(|b| *b == b'=') else {
+                continue;
+            };
+            if line[..equals].trim_ascii().eq_ignore_ascii_case(key) {
+                let v = line[equals + 1..].trim_ascii();
+                value = Some(
+                    v.strip_prefix(b"\"")
+                        .and_then(|v| v.strip_suffix(b"\""))
+                        .unwrap_or(v),
+                );
+            }
+        }
+    }
+    value
+}
+
+/// Joins two byte strings into a null-terminated path
+pub fn with_suffix(path: &[u8], suffix: &[u8]) -> Vec<u8> {
+    let mut joined = Vec::with_capacity(path.len() + suffix.len() + 1);
+    joined.extend_from_slice(path);
+    joined.extend_from_slice(suffix);
+    joined.push(0);
+    joined
+}
+
+fn exists(path: &[u8]) -> bool {
+    syscalls::faccessat(libc::AT_FDCWD, CStr::from_bytes(path), libc::F_OK).is_ok()
+}
@@ -0,0 +1,126 @@
+/// Matches a file name against a shell wildcard pattern, like fnmatch with FNM_PERIOD does:
+/// a leading dot has to be matched by a literal dot, not by `*`, `?` or a bracket expression.
+pub fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    if name.first() == Some(&b'.') && !(pattern.starts_with(b".") || pattern.starts_with(b"\\.")) {
+        return false;
+    }
+
+    let mut p = 0;
+    let mut n = 0;
+    // Where to resume after the most recent *, if the rest of the pattern doesn't match
+    let mut backtrack = None;
+    while n < name.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            backtrack = Some((p, n));
+            continue;
+        }
+        if let Some(len) = match_one(&pattern[p..], name[n]) {
+            p += len;
+            n += 1;
+            continue;
+        }
+        // Let the * consume one more byte and try again
+        match backtrack {
+            Some((star_p, star_n)) => {
+                p = star_p;
+                n = star_n + 1;
+                backtrack = Some((star_p, n));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|b| *b == b'*')
+}
+
+/// If the first element of the pattern matches the byte, the length of that element
+fn match_one(pattern: &[u8], b: u8) -> Option<usize> {
+    match pattern {
+        [b'?', ..] => Some(1),
+        [b'[', set @ ..] => match match_bracket(set, b) {
+            Some((matched, len)) => matched.then_some(len + 1),
+            // Without a closing ], the [ is just a [
+            None => (b == b'[').then_some(1),
+        },
+        [b'\\', escaped, ..] => (b == *escaped).then_some(2),
+        [c, ..] => (b == *c).then_some(1),
+        [] => None,
+    }
+}
+
+/// Matches the inside of a bracket expression like `[a-z_]` or `[!0-9]`, returning whether it
+/// matched and its length including the closing ], or None if it never closes
+fn match_bracket(set: &[u8], b: u8) -> Option<(bool, usize)> {
+    let negated = matches!(set.first(), Some(b'!' | b'^'));
+    let mut i = negated as usize;
+    let mut matched = false;
+    let start = i;
+    loop {
+        let mut low = *set.get(i)?;
+        // A ] right at the start is part of the set
+        if low == b']' && i != start {
+            return Some((matched != negated, i + 1));
+        }
+        if low == b'\\' {
+            i += 1;
+            low = *set.get(i)?;
+        }
+        i += 1;
+        let mut high = low;
+        if set.get(i) == Some(&b'-') && set.get(i + 1).is_some_and(|c| *c != b']') {
+            i += 1;
+            if set[i] == b'\\' {
+                i += 1;
+            }
+            high = *set.get(i)?;
+            i += 1;
+        }
+        matched |= (low..=high).contains(&b);
+    }
+}
+
+/// Matches a path against a gitignore pattern. Unlike with `matches`, `*`, `?` and bracket
+/// expressions never match a slash and dots aren't special, but `**` between slashes matches any
+/// number of directories.
+pub fn matches_path(pattern: &[u8], path: &[u8]) -> bool {
+    match_path_from(pattern, path, true)
+}
+
+fn match_path_from(pattern: &[u8], path: &[u8], segment_start: bool) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        // A trailing /** matches everything inside
+        [b'*', b'*'] if segment_start => true,
+        [b'*', b'*', b'/', rest @ ..] if segment_start => {
+            match_path_from(rest, path, true)
+                || path
+                    .iter()
+                    .enumerate()
+                    .any(|(i, b)| *b == b'/' && match_path_from(rest, &path[i + 1..], true))
+        }
+        [b'*', ..] => {
+            let rest = &pattern[pattern.iter().take_while(|b| **b == b'*').count()..];
+            for i in 0..=path.len() {
+                if match_path_from(rest, &path[i..], false) {
+                    return true;
+                }
+                if path.get(i) == Some(&b'/') {
+                    return false;
+                }
+            }
+            false
+        }
+        _ => {
+            let Some((&b, rest)) = path.split_first() else {
+                return false;
+            };
+            if b == b'/' && pattern[0] != b'/' {
+                return false;
+            }
+            match match_one(pattern, b) {
+                Some(len) => match_path_from(&pattern[len..], rest, b == b'/'),
+                None => false,
+            }
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Just enough zlib to read git's objects: the wrapper from RFC 1950 around the deflate format
+//! from RFC 1951. Decoding goes one bit at a time, which is slow but small, and the objects we
+//! read are only commits and trees.
+
+use alloc::vec::Vec;
+
+/// Decompresses a zlib stream, which may be followed by anything else. size is how big the
+/// output is expected to be, and is only used to allocate up front.
+pub fn inflate(data: &[u8], size: usize) -> Option<Vec<u8>> {
+    let (&cmf, &flg) = (data.first()?, data.get(1)?);
+    // Only deflate is defined, and git never uses a preset dictionary
+    if cmf & 0xf != 8 || flg & 0x20 != 0 || (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+        return None;
+    }
+    let mut bits = Bits {
+        data: &data[2..],
+        buf: 0,
+        count: 0,
+    };
+    let mut out = Vec::with_capacity(size);
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => stored(&mut bits, &mut out)?,
+            1 => {
+                let (lengths, distances) = fixed_tables();
+                codes(&mut bits, &mut out, &lengths, &distances)?;
+            }
+            2 => {
+                let (lengths, distances) = dynamic_tables(&mut bits)?;
+                codes(&mut bits, &mut out, &lengths, &distances)?;
+            }
+            _ => return None,
+        }
+        if last {
+            break;
+        }
+    }
+    // The stream ends with the Adler-32 checksum of what it decompresses to, starting at the next
+    // byte
+    let checksum = bits.data.get(..4)?;
+    if u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) != adler32(&out) {
+        return None;
+    }
+    Some(out)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // Sums of this many bytes can't overflow before they're reduced
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += u32::from(*byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    b << 16 | a
+}
+
+struct Bits<'a> {
+    data: &'a [u8],
+    buf: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    /// The next n bits, least significant first
+    fn take(&mut self, n: u32) -> Option<u32> {
+        while self.count < n {
+            let (byte, rest) = self.data.split_first()?;
+            self.data = rest;
+            self.buf |= u32::from(*byte) << self.count;
+            self.count += 8;
+        }
+        let value = self.buf & ((1 << n) - 1);
+        self.buf >>= n;
+        self.count -= n;
+        Some(value)
+    }
+}
+
+/// A canonical Huffman code, stored as how many codes there are of each length and the symbols
+/// in order of their codes
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Option<Self> {
+        let mut counts = [0u16; 16];
+        for len in lengths {
+            counts[usize::from(*len)] += 1;
+        }
+        counts[0] = 0;
+        // Make sure no length has more codes than can fit
+        let mut left = 1i32;
+        for count in &counts[1..] {
+            left = (left << 1) - i32::from(*count);
+            if left < 0 {
+                return None;
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = alloc::vec![0; lengths.len()];
+        for (symbol, len) in lengths.iter().enumerate() {
+            if *len != 0 {
+                symbols[usize::from(offsets[usize::from(*len)])] = symbol as u16;
+                offsets[usize::from(*len)] += 1;
+            }
+        }
+        Some(Self { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Option<u16> {
+        // Codes are stored most significant bit first, so they're read one bit at a time
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for count in &self.counts[1..] {
+            code |= bits.take(1)? as i32;
+            let count = i32::from(*count);
+            if code - first < count {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+fn stored(bits: &mut Bits, out: &mut Vec<u8>) -> Option<()> {
+    // Stored blocks start at a byte boundary, and any bits left over are padding
+    bits.buf = 0;
+    bits.count = 0;
+    let len = bits.take(16)?;
+    if bits.take(16)? != !len & 0xffff {
+        return None;
+    }
+    let len = len as usize;
+    out.extend_from_slice(bits.data.get(..len)?);
+    bits.data = &bits.data[len..];
+    Some(())
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [8u8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    // These lengths are a complete code, so they can't fail
+    (
+        Huffman::new(&lengths).unwrap(),
+        Huffman::new(&[5; 30]).unwrap(),
+    )
+}
+
+fn dynamic_tables(bits: &mut Bits) -> Option<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+    let literals = bits.take(5)? as usize + 257;
+    let distances = bits.take(5)? as usize + 1;
+    let code_lengths = bits.take(4)? as usize + 4;
+    if literals > 286 || distances > 30 {
+        return None;
+    }
+
+    // The lengths of the two codes are themselves compressed with a third code
+    let mut lengths = [0u8; 19];
+    for i in &ORDER[..code_lengths] {
+        lengths[*i] = bits.take(3)? as u8;
+    }
+    let code = Huffman::new(&lengths)?;
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (len, repeat) = match code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last()?, 3 + bits.take(2)?),
+            17 => (0, 3 + bits.take(3)?),
+            _ => (0, 11 + bits.take(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(len);
+        }
+    }
+    if lengths.len() > literals + distances || lengths[256] == 0 {
+        return None;
+    }
+    Some((
+        Huffman::new(&lengths[..literals])?,
+        Huffman::new(&lengths[literals..])?,
+    ))
+}
+
+fn codes(bits: &mut Bits, out: &mut Vec<u8>, lengths: &Huffman, distances: &Huffman) -> Option<()> {
+    const LENGTH_BASE: [u16; 29] = [
+        3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+        131, 163, 195, 227, 258,
+    ];
+    const LENGTH_EXTRA: [u8; 29] = [
+        0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+    ];
+    const DISTANCE_BASE: [u16; 30] = [
+        1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+        2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+    ];
+    const DISTANCE_EXTRA: [u8; 30] = [
+        0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12,
+        13, 13,
+    ];
+    loop {
+        let symbol = usize::from(lengths.decode(bits)?);
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Some(()),
+            _ => {
+                let i = symbol - 257;
+                let len = usize::from(*LENGTH_BASE.get(i)?)
+                    + bits.take(u32::from(LENGTH_EXTRA[i]))? as usize;
+                let i = usize::from(distances.decode(bits)?);
+                let distance = usize::from(*DISTANCE_BASE.get(i)?)
+                    + bits.take(u32::from(DISTANCE_EXTRA[i]))? as usize;
+                let start = out.len().checked_sub(distance)?;
+                // The copy may overlap what it's writing, so it goes a byte at a time
+                for i in start..start + len {
+                    out.push(out[i]);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use crate::{
+    cli::{App, DisplayMode},
+    directory::{DirEntry, DirEntryExt},
+    output::{OutputBuffer, Writable},
+    syscalls, Status,
+};
+use alloc::vec::Vec;
+use veneer::fs::{DType, Directory};
+
+/// Prints the entries of one directory for --format=json as a single array, or for
+/// --format=ndjson as one object per line. path is the directory they're in, which is empty for
+/// names from the command line.
+pub fn write_entries(
+    entries: &[(DirEntry, Option<Status>)],
+    dir: &Directory,
+    path: &[u8],
+    app: &mut App,
+) {
+    let lines = app.display_mode == (DisplayMode::Json { lines: true });
+    if !lines {
+        app.out.push(b'[');
+    }
+    for (i, entry) in entries.iter().enumerate() {
+        if !lines && i > 0 {
+            app.out.push(b',');
+        }
+        write_entry(entry, dir, path, app);
+        if lines {
+            app.out.push(b'\n');
+        }
+    }
+    if !lines {
+        app.out.write(b"]\n");
+    }
+}
+
+/// Stands in for the usual error message, so that errors can be told apart from entries without
+/// scraping stderr
+pub fn write_error(out: &mut OutputBuffer, item: &[u8], errno: i32) {
+    let mut record = Object::new(out);
+    record.string(b"type", b"error");
+    record.string(b"path", item);
+    record.number(b"errno", errno.into());
+    record.end();
+    out.push(b'\n');
+}
+
+fn write_entry(entry: &(DirEntry, Option<Status>), dir: &Directory, path: &[u8], app: &mut App) {
+    let name = entry.name().as_bytes();
+    let mut full_path = path.to_vec();
+    if !full_path.is_empty() && full_path.last() != Some(&b'/') {
+        full_path.push(b'/');
+    }
+    full_path.extend_from_slice(name);
+
+    let owners = entry
+        .1
+        .as_ref()
+        .map(|status| (app.getpwuid(status.uid), app.getgrgid(status.gid)));
+
+    let mut record = Object::new(&mut app.out);
+    record.string(b"name", name);
+    record.string(b"path", &full_path);
+    if let Some(kind) = entry_type(entry) {
+        record.string(b"type", kind);
+    }
+    // Without a Status, the error has already been reported
+    let (Some(status), Some((user, group))) = (&entry.1, owners) else {
+        record.end();
+        return;
+    };
+    record.number(b"mode", (status.mode & 0o7777).into());
+    record.number(b"nlink", status.links as i64);
+    record.number(b"uid", status.uid.into());
+    record.optional_string(b"user", user);
+    record.number(b"gid", status.gid.into());
+    record.optional_string(b"group", group);
+    record.number(b"size", status.size);
+    record.number(b"blocks", status.blocks);
+    record.number(b"inode", status.inode as i64);
+    for (key, time, nsec) in [
+        (&b"atime"[..], status.atime, status.atime_nsec),
+        (b"mtime", status.mtime, status.mtime_nsec),
+        (b"ctime", status.ctime, status.ctime_nsec),
+    ] {
+        let mut timestamp = Object::new(record.key(key));
+        timestamp.number(b"sec", time);
+        timestamp.number(b"nsec", nsec);
+        timestamp.end();
+    }
+    if status.mode & libc::S_IFMT == libc::S_IFLNK {
+        let mut buf = [0u8; 1024];
+        if let Ok(target) = syscalls::readlinkat(dir.raw_fd(), entry.name(), &mut buf) {
+            record.string(b"target", target);
+        }
+    }
+    record.end();
+}
+
+fn entry_type(entry: &(DirEntry, Option<Status>)) -> Option<&'static [u8]> {
+    let format = match &entry.1 {
+        Some(status) => status.mode & libc::S_IFMT,
+        None => match entry.0.d_type {
+            DType::DIR => libc::S_IFDIR,
+            DType::REG => libc::S_IFREG,
+            DType::LNK => libc::S_IFLNK,
+            DType::FIFO => libc::S_IFIFO,
+            DType::SOCK => libc::S_IFSOCK,
+            DType::BLK => libc::S_IFBLK,
+            DType::CHR => libc::S_IFCHR,
+            _ => return None,
+        },
+    };
+    Some(match format {
+        libc::S_IFDIR => b"directory",
+        libc::S_IFREG => b"file",
+        libc::S_IFLNK => b"symlink",
+        libc::S_IFIFO => b"fifo",
+        libc::S_IFSOCK => b"socket",
+        libc::S_IFBLK => b"block_device",
+        libc::S_IFCHR => b"char_device",
+        _ => b"unknown",
+    })
+}
+
+/// Writes the members of a JSON object, taking care of the commas between them
+struct Object<'a> {
+    out: &'a mut OutputBuffer,
+    empty: bool,
+}
+
+impl<'a> Object<'a> {
+    fn new(out: &'a mut OutputBuffer) -> Self {
+        out.push(b'{');
+        Self { out, empty: true }
+    }
+
+    /// Starts a member, leaving the buffer ready for its value
+    fn key(&mut self, key: &[u8]) -> &mut OutputBuffer {
+        if !self.empty {
+            self.out.push(b',');
+        }
+        self.empty = false;
+        self.out.push(b'"').write(key).write(b"\":")
+    }
+
+    fn number(&mut self, key: &[u8], value: i64) {
+        let out = self.key(key);
+        if value < 0 {
+            out.push(b'-');
+        }
+        value.unsigned_abs().write(out);
+    }
+
+    /// JSON strings have to be Unicode, so a name that isn't UTF-8 is written with U+FFFD in
+    /// place of the bytes that aren't, and its exact bytes are added as an array under
+    /// key_bytes
+    fn string(&mut self, key: &[u8], value: &[u8]) {
+        let out = self.key(key);
+        out.push(b'"');
+        let mut lossy = false;
+        for chunk in value.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                match c {
+                    '"' => out.write(b"\\\""),
+                    '\\' => out.write(b"\\\\"),
+                    '\n' => out.write(b"\\n"),
+                    '\r' => out.write(b"\\r"),
+                    '\t' => out.write(b"\\t"),
+                    '\0'..='\x1f' => {
+                        let hex = b"0123456789abcdef";
+                        out.write(b"\\u00")
+                            .push(hex[c as usize >> 4])
+                            .push(hex[c as usize & 0xf])
+                    }
+                    _ => out.write(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                };
+            }
+            if !chunk.invalid().is_empty() {
+                lossy = true;
+                out.write("\u{FFFD}".as_bytes());
+            }
+        }
+        out.push(b'"');
+
+        if lossy {
+            let mut key_bytes = Vec::with_capacity(key.len() + 6);
+            key_bytes.extend_from_slice(key);
+            key_bytes.extend_from_slice(b"_bytes");
+            let out = self.key(&key_bytes);
+            out.push(b'[');
+            for (i, b) in value.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                (*b as u64).write(out);
+            }
+            out.push(b']');
+        }
+    }
+
+    /// Names we couldn't look up are null
+    fn optional_string(&mut self, key: &[u8], value: &[u8]) {
+        if value.is_empty() {
+            self.key(key).write(b"null");
+        } else {
+            self.string(key, value);
+        }
+    }
+
+    fn end(self) {
+        self.out.push(b'}');
+    }
+}
@@ -5,21 +5,31 @@ extern crate alloc;
 
 mod cli;
 mod directory;
+mod git;
+mod gitignore;
+mod glob;
+mod inflate;
+mod json;
 mod output;
 mod style;
+mod syscalls;
 mod time;
+mod tree;
 mod utils;
+mod xattr;
 
 use crate::{
     cli::{App, Args, DisplayMode, ShowAll, SortField},
     directory::{DirEntry, DirEntryExt},
     output::*,
     style::Style,
+    utils::memcmp,
 };
 use alloc::vec::Vec;
+use core::cmp::Ordering;
 use veneer::{
-    fs::{DType, Directory},
-    syscalls, CStr, Error,
+    fs::{DType, Directory, DirectoryContents},
+    CStr, Error,
 };
 
 #[veneer::main]
@@ -27,6 +37,11 @@ fn main() -> Result<(), Error> {
     let mut app = App::DEFAULT;
     app.init()?;
 
+    if matches!(app.args, Args::None) && matches!(app.display_mode, DisplayMode::Tree { .. }) {
+        tree::write_tree(b".", &mut app);
+        return Ok(());
+    }
+
     if matches!(app.args, Args::None) && !app.recurse {
         let dir = Directory::open(CStr::from_bytes(b".\0"))?;
         list_dir_contents(&mut Vec::new(), &mut Vec::new(), &dir, &mut app);
@@ -50,7 +65,7 @@ fn main() -> Result<(), Error> {
                 )),
                 Err(_) => {
                     if let Err(err) = veneer::syscalls::fstatat(libc::AT_FDCWD, arg) {
-                        access_error(&arg, err);
+                        access_error(&app, &arg, err);
                     } else {
                         files.push((
                             DirEntry {
@@ -81,16 +96,10 @@ fn main() -> Result<(), Error> {
         let dir = Directory::open(CStr::from_bytes(b".\0")).unwrap();
         if app.needs_details {
             for e in &mut files {
-                let status = if app.follow_symlinks == cli::FollowSymlinks::Always {
-                    syscalls::fstatat(dir.raw_fd(), e.name())
-                } else {
-                    syscalls::lstatat(dir.raw_fd(), e.name())
-                }
-                .map(|status| app.convert_status(status));
-                match status {
+                match app.stat(dir.raw_fd(), e.name()) {
                     Ok(s) => e.1 = Some(s),
                     Err(err) => {
-                        access_error(&e.name(), err);
+                        access_error(&app, &e.name(), err);
                     }
                 }
             }
@@ -100,17 +109,30 @@ fn main() -> Result<(), Error> {
 
         match app.display_mode {
             DisplayMode::Grid(width) => write_grid(&files, &dir, &mut app, width),
-            DisplayMode::Long => write_details(&files, &dir, &mut app),
-            DisplayMode::SingleColumn => write_single_column(&files, &dir, &mut app),
+            DisplayMode::Long | DisplayMode::Tree { long: true } => {
+                write_details(&files, &dir, &mut app)
+            }
+            DisplayMode::SingleColumn | DisplayMode::Tree { long: false } => {
+                write_single_column(&files, &dir, &mut app)
+            }
             DisplayMode::Stream => write_stream(&files, &dir, &mut app),
+            DisplayMode::Json { .. } => json::write_entries(&files, &dir, b"", &mut app),
         }
     }
 
-    if !dirs.is_empty() && !files.is_empty() {
+    let json = matches!(app.display_mode, DisplayMode::Json { .. });
+    if !dirs.is_empty() && !files.is_empty() && !json {
         app.out.push(b'\n');
     }
 
     for (n, (name, dir)) in dirs.iter().enumerate() {
+        if let DisplayMode::Tree { .. } = app.display_mode {
+            tree::write_tree(name.as_bytes(), &mut app);
+            if n != dirs.len() - 1 {
+                app.out.push(b'\n');
+            }
+            continue;
+        }
         let mut path = Vec::new();
         path.extend(name.as_bytes());
         let status = syscalls::fstat(dir.raw_fd()).unwrap();
@@ -121,7 +143,7 @@ fn main() -> Result<(), Error> {
         list_dir_contents(&mut stack, &mut path, dir, &mut app);
         // When recursing the recursion handles newlines, if not we need to check if we're on the
         // last and print a newline
-        if !app.recurse && (n != dirs.len() - 1) {
+        if !app.recurse && (n != dirs.len() - 1) && !json {
             app.out.push(b'\n');
         }
     }
@@ -129,31 +151,56 @@ fn main() -> Result<(), Error> {
     Ok(())
 }
 
+/// Without any sort keys, entries stay in directory order. GNU ls doesn't group directories first
+/// then either, so neither do we.
 fn sort_entries(entries: &mut [(DirEntry, Option<Status>)], app: &App) {
-    if let Some(field) = app.sort_field {
+    if let Some(keys) = &app.sort_keys {
         entries.sort_unstable_by(|a, b| {
-            let mut ordering = match field {
-                SortField::Time => b
-                    .time()
-                    .cmp(&a.time())
-                    .then_with(|| vercmp(a.name(), b.name())),
-                SortField::Size => {
-                    b.1.clone()
-                        .unwrap_or_default()
-                        .size
-                        .cmp(&a.1.clone().unwrap_or_default().size)
-                        .then_with(|| vercmp(a.name(), b.name()))
-                }
-                SortField::Name => vercmp(a.name(), b.name()),
-            };
+            let mut ordering = keys
+                .iter()
+                .fold(Ordering::Equal, |ordering, key| {
+                    ordering.then_with(|| compare_entries(*key, a, b, app))
+                })
+                .then_with(|| vercmp(a.name(), b.name()));
             if app.reverse_sorting {
                 ordering = ordering.reverse();
             }
+            // Directories stay first even when the order is reversed
+            if app.group_directories_first {
+                ordering = b.is_directory().cmp(&a.is_directory()).then(ordering);
+            }
             ordering
         });
     }
 }
 
+fn compare_entries(
+    key: SortField,
+    a: &(DirEntry, Option<Status>),
+    b: &(DirEntry, Option<Status>),
+    app: &App,
+) -> Ordering {
+    let size = |e: &(DirEntry, Option<Status>)| e.1.as_ref().map_or(0, |s| s.size);
+    match key {
+        SortField::Name => vercmp(a.name(), b.name()),
+        SortField::Size => size(b).cmp(&size(a)),
+        // None is less than any time, so entries without one come after the oldest
+        SortField::Time => b.time().cmp(&a.time()),
+        SortField::Extension => memcmp(extension(a.name()), extension(b.name())),
+        SortField::Width => app
+            .out
+            .name_len(a.name().as_bytes())
+            .cmp(&app.out.name_len(b.name().as_bytes())),
+        SortField::Inode => a.inode().cmp(&b.inode()),
+    }
+}
+
+/// Like GNU ls, everything from the last dot is the extension, so .bashrc is all extension
+fn extension(name: CStr<'_>) -> &[u8] {
+    let name = name.as_bytes();
+    &name[name.iter().rposition(|b| *b == b'.').unwrap_or(name.len())..]
+}
+
 fn list_dir_contents(
     stack: &mut Vec<(libc::dev_t, libc::ino_t)>,
     path: &mut Vec<u8>,
@@ -163,31 +210,13 @@ fn list_dir_contents(
     let contents = match dir.read() {
         Ok(c) => c,
         Err(err) => {
-            access_error(path, err);
+            // Directories we recursed into still have the null terminator we opened them with
+            access_error(app, path.strip_suffix(b"\0").unwrap_or(path), err);
             return;
         }
     };
-    let hint = contents.iter().size_hint();
-    let mut entries = Vec::with_capacity(hint.1.unwrap_or(hint.0));
-
-    for e in contents.iter() {
-        match app.show_all {
-            ShowAll::No => {
-                if e.name().get(0) == Some(b'.') {
-                    continue;
-                }
-            }
-            ShowAll::Almost => {
-                let name = e.name().as_bytes();
-                if name == b"." || name == b".." {
-                    continue;
-                }
-            }
-            ShowAll::Yes => {}
-        }
-        entries.push((e.into(), None));
-    }
 
+    let json = matches!(app.display_mode, DisplayMode::Json { .. });
     if matches!(app.args, Args::Multiple) || app.recurse {
         if path.len() > 1 && path.last() == Some(&0) {
             path.pop();
@@ -195,38 +224,31 @@ fn list_dir_contents(
         if path.len() > 1 && path.last() == Some(&b'/') {
             path.pop();
         }
-        app.out.write(path).write(b":\n");
-    }
-
-    if app.needs_details {
-        for e in &mut entries {
-            let status = if app.follow_symlinks == cli::FollowSymlinks::Always {
-                syscalls::fstatat(dir.raw_fd(), e.name())
-            } else {
-                syscalls::lstatat(dir.raw_fd(), e.name())
-            }
-            .map(|status| app.convert_status(status));
-            match status {
-                Ok(s) => e.1 = Some(s),
-                Err(err) => {
-                    access_error(&e.name(), err);
-                }
-            }
+        // Every JSON record has its path, so there are no headers
+        if !json {
+            app.out.write_name(path).write(b":\n");
         }
     }
 
-    sort_entries(&mut entries, app);
+    enter_dir(dir, path, app);
+    let entries = read_entries(&contents, dir, app, true);
 
     match app.display_mode {
         DisplayMode::Grid(width) => write_grid(&entries, dir, app, width),
-        DisplayMode::Long => write_details(&entries, dir, app),
-        DisplayMode::SingleColumn => write_single_column(&entries, dir, app),
+        DisplayMode::Long | DisplayMode::Tree { long: true } => write_details(&entries, dir, app),
+        DisplayMode::SingleColumn | DisplayMode::Tree { long: false } => {
+            write_single_column(&entries, dir, app)
+        }
         DisplayMode::Stream => write_stream(&entries, dir, app),
+        DisplayMode::Json { .. } => {
+            json::write_entries(&entries, dir, path.strip_suffix(b"\0").unwrap_or(path), app)
+        }
     }
     app.out.flush();
-
     if app.recurse {
-        app.out.push(b'\n');
+        if !json {
+            app.out.push(b'\n');
+        }
         for e in entries
             .iter()
             .filter_map(|(e, status)| {
@@ -262,7 +284,7 @@ fn list_dir_contents(
                     }
                 }
                 Err(err) => {
-                    access_error(&path[..path.len() - 1], err);
+                    access_error(app, &path[..path.len() - 1], err);
                 }
             }
             while path.last() != Some(&b'/') {
@@ -273,11 +295,87 @@ fn list_dir_contents(
     if path.last() == Some(&b'/') {
         path.pop();
     }
+
+    leave_dir(app);
+}
+
+/// Sets up everything that depends on which directory is being listed
+fn enter_dir(dir: &Directory, path: &[u8], app: &mut App) {
+    if let Some(git_ignore) = &mut app.git_ignore {
+        git_ignore.enter(dir, path);
+    }
+    if app.git_status {
+        git::load_repository(app);
+    }
+    if let Some(links) = &mut app.out.hyperlinks {
+        links.set_dir(path);
+    }
+}
+
+fn leave_dir(app: &mut App) {
+    if let Some(git_ignore) = &mut app.git_ignore {
+        git_ignore.leave();
+    }
+}
+
+/// The entries of a directory that should be listed, with their Status if we need it, in order
+fn read_entries<'a>(
+    contents: &'a DirectoryContents,
+    dir: &Directory,
+    app: &App,
+    report_errors: bool,
+) -> Vec<(DirEntry<'a>, Option<Status>)> {
+    let hint = contents.iter().size_hint();
+    let mut entries = Vec::with_capacity(hint.1.unwrap_or(hint.0));
+
+    for e in contents.iter() {
+        match app.show_all {
+            ShowAll::No => {
+                if e.name().get(0) == Some(b'.') {
+                    continue;
+                }
+            }
+            ShowAll::Almost => {
+                let name = e.name().as_bytes();
+                if name == b"." || name == b".." {
+                    continue;
+                }
+            }
+            ShowAll::Yes => {}
+        }
+        if app.ignores(e.name().as_bytes())
+            || (app.git_ignore.as_ref())
+                .is_some_and(|g| g.hides(e.name().as_bytes(), e.d_type() == DType::DIR))
+        {
+            continue;
+        }
+        entries.push((e.into(), None));
+    }
+
+    if app.needs_details {
+        let mut xattrs = true;
+        for e in &mut entries {
+            match app.stat_in(dir.raw_fd(), e.name(), &mut xattrs) {
+                Ok(s) => e.1 = Some(s),
+                Err(err) if report_errors => {
+                    access_error(app, &e.name(), err);
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    sort_entries(&mut entries, app);
+    entries
 }
 
 #[inline(never)]
-fn access_error(item: &[u8], error: Error) {
+fn access_error(app: &App, item: &[u8], error: Error) {
     let mut out = crate::output::OutputBuffer::to_fd(2);
+    if let DisplayMode::Json { .. } = app.display_mode {
+        json::write_error(&mut out, item, error.0);
+        return;
+    }
     out.write(&b"Unable to access '"[..])
         .write(item)
         .write(&b"': OS Error "[..]);
@@ -295,6 +393,33 @@ pub struct Status {
     pub block_size: libc::blksize_t,
     pub uid: libc::uid_t,
     pub gid: libc::gid_t,
-    pub time: libc::time_t,
+    /// The time --time asks for, in seconds and nanoseconds, or None for a birth time the
+    /// filesystem doesn't have
+    pub time: Option<(libc::time_t, i64)>,
+    /// The modification time whatever --time says, for comparing against the git index
+    pub mtime: libc::time_t,
+    pub mtime_nsec: i64,
+    /// All three times are in the JSON output
+    pub atime: libc::time_t,
+    pub atime_nsec: i64,
+    pub ctime: libc::time_t,
+    pub ctime_nsec: i64,
     pub inode: libc::ino_t,
+    /// The device a block or character special file refers to
+    pub rdev: libc::dev_t,
+    /// The SELinux context, which is only looked up for -Z
+    pub context: Option<Vec<u8>>,
+    /// The names of the extended attributes, each null-terminated, which are only listed in long
+    /// format
+    pub xattrs: Option<Vec<u8>>,
+    /// + for an ACL or . for an SELinux context, which is only looked up in long format
+    pub mode_indicator: Option<u8>,
+    /// Decoded file capabilities, which are only looked up for --capabilities
+    pub capabilities: Option<Vec<u8>>,
+}
+
+impl Status {
+    pub fn is_device(&self) -> bool {
+        matches!(self.mode & libc::S_IFMT, libc::S_IFBLK | libc::S_IFCHR)
+    }
 }
@@ -1,15 +1,20 @@
 use crate::{
-    cli::App,
+    cli::{App, FollowSymlinks, QuotingStyle},
     directory::{DirEntry, DirEntryExt},
-    utils::Buffer,
+    syscalls,
+    utils::{BlockSize, Buffer, SliceExt},
+    xattr::{self, XattrListing},
     Status, Style,
 };
 use alloc::vec::Vec;
 use core::ffi::c_int;
-use veneer::{fs::Directory, syscalls, CStr};
+use veneer::{fs::Directory, CStr};
 
-use libc::{S_IRGRP, S_IROTH, S_IRUSR, S_IWGRP, S_IWOTH, S_IWUSR, S_IXGRP, S_IXOTH, S_IXUSR};
-use unicode_width::UnicodeWidthStr;
+use libc::{
+    S_IRGRP, S_IROTH, S_IRUSR, S_ISGID, S_ISUID, S_ISVTX, S_IWGRP, S_IWOTH, S_IWUSR, S_IXGRP,
+    S_IXOTH, S_IXUSR,
+};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 #[macro_export]
 macro_rules! print {
@@ -20,61 +25,187 @@ macro_rules! print {
     }};
 }
 
-fn print_rwx(app: &mut App, mode: u32, read_mask: u32, write_mask: u32, execute_mask: u32) {
+/// Prints one rwx triplet. The setuid, setgid, and sticky bits share the execute position: they
+/// are shown as s or t when the execute bit is also set, and as S or T when it is not.
+fn print_rwx(app: &mut App, mode: u32, masks: [u32; 3], special_mask: u32, special: u8) {
     use Style::*;
 
-    for (mask, color, chr) in [
-        (read_mask, YellowBold, b'r'),
-        (write_mask, RedBold, b'w'),
-        (execute_mask, GreenBold, b'x'),
-    ] {
+    for (mask, color, chr) in [(masks[0], YellowBold, b'r'), (masks[1], RedBold, b'w')] {
         if mode & mask > 0 {
             app.out.style(color).push(chr);
         } else {
             app.out.style(Gray).push(b'-');
         }
     }
+
+    let (color, chr) = match (mode & masks[2] > 0, mode & special_mask > 0) {
+        (true, true) => (MagentaBold, special),
+        (false, true) => (MagentaBold, special.to_ascii_uppercase()),
+        (true, false) => (GreenBold, b'x'),
+        (false, false) => (Gray, b'-'),
+    };
+    app.out.style(color).push(chr);
 }
 
 pub fn write_details(entries: &[(DirEntry, Option<Status>)], dir: &Directory, app: &mut App) {
-    use Style::*;
+    let layout = DetailsLayout::measure(entries, app);
+    print_total_blocks(entries, app);
+    for direntry in entries {
+        layout.write_row(direntry, dir, app, b"", b"");
+    }
+}
 
-    let mut longest_name_len = 1;
-    let mut longest_group_len = 1;
-    let mut largest_size = 0;
-    let mut largest_links = 0;
-    let mut blocks = 0;
-    let mut inode_len = 0;
-    let mut blocks_len = 0;
+/// The widths of the columns in long format, which --tree measures across a whole tree
+#[derive(Clone, Copy)]
+pub struct DetailsLayout {
+    owner_len: usize,
+    group_len: usize,
+    /// Not counting devices, which print their major and minor numbers instead
+    size_len: usize,
+    major_len: usize,
+    minor_len: usize,
+    links_len: usize,
+    inode_len: usize,
+    blocks_len: usize,
+    context_len: usize,
+    /// Zero if no entry has capabilities, in which case there's no column for them
+    capabilities_len: usize,
+    /// Whether any entry has a mode_indicator, which makes room for one after every mode
+    mode_indicators: bool,
+    quote_padding: bool,
+    /// Recent and old times are formatted differently
+    current_time: libc::time_t,
+}
 
-    for status in entries.iter().filter_map(|e| e.1.as_ref()) {
-        if app.print_owner {
-            longest_name_len = longest_name_len.max(app.getpwuid(status.uid).len());
+impl DetailsLayout {
+    pub fn measure(entries: &[(DirEntry, Option<Status>)], app: &App) -> Self {
+        let mut layout = Self {
+            owner_len: 1,
+            group_len: 1,
+            size_len: 0,
+            major_len: 0,
+            minor_len: 0,
+            links_len: 0,
+            inode_len: 0,
+            blocks_len: 0,
+            context_len: 0,
+            capabilities_len: 0,
+            mode_indicators: false,
+            quote_padding: needs_quote_padding(entries, app),
+            current_time: syscalls::gettimeofday().unwrap().tv_sec,
+        };
+        let mut largest_links = 0;
+        let mut largest_inode = 0;
+        let mut buf = Buffer::new();
+
+        for status in entries.iter().filter_map(|e| e.1.as_ref()) {
+            if app.print_owner {
+                layout.owner_len = layout.owner_len.max(app.getpwuid(status.uid).len());
+            }
+
+            if app.print_group {
+                layout.group_len = layout.group_len.max(app.getgrgid(status.gid).len());
+            }
+
+            // Devices have no size, so their major and minor numbers are printed instead.
+            // Human-readable sizes don't get wider as they get larger, so measure every one.
+            if status.is_device() {
+                let major = buf.format(libc::major(status.rdev).into()).len();
+                layout.major_len = layout.major_len.max(major);
+                let minor = buf.format(libc::minor(status.rdev).into()).len();
+                layout.minor_len = layout.minor_len.max(minor);
+            } else {
+                let size = buf.format_size(status.size as u64, app.file_block_size);
+                layout.size_len = layout.size_len.max(size.len());
+            }
+            if app.display_size_in_blocks {
+                let blocks = buf.format_size(status.blocks as u64 * BLOCK_BYTES, app.block_size);
+                layout.blocks_len = layout.blocks_len.max(blocks.len());
+            }
+            layout.mode_indicators |= status.mode_indicator.is_some();
+            if let Some(capabilities) = &status.capabilities {
+                layout.capabilities_len = layout.capabilities_len.max(capabilities.len());
+            }
+            largest_links = largest_links.max(status.links);
+            largest_inode = largest_inode.max(status.inode);
         }
 
-        if app.print_group {
-            longest_group_len = longest_group_len.max(app.getgrgid(status.gid).len());
+        if app.print_context {
+            layout.context_len = context_width(entries);
         }
+        layout.links_len = buf.format(largest_links).len();
+        layout.inode_len = buf.format(largest_inode).len();
+        layout
+    }
 
-        largest_size = largest_size.max(status.size as usize);
-        largest_links = largest_links.max(status.links as usize);
-        inode_len = inode_len.max(status.inode as usize);
-        blocks_len = blocks_len.max(status.blocks as usize);
-        blocks += status.blocks * status.block_size / 8192;
+    /// How wide a recent time is in the current --time-style
+    fn time_width(&self, app: &App) -> usize {
+        let mut formatted = Vec::new();
+        app.convert_to_localtime(self.current_time, 0)
+            .format(app.time_style.recent, &mut formatted);
+        // GNU ls pads by bytes here, even in a UTF-8 locale
+        formatted.len()
     }
 
-    print!(app, "total ", blocks, "\n");
+    /// How far into a row its name starts, given how many bytes its time took
+    fn name_column(&self, app: &App, time_len: usize, git_status: bool) -> usize {
+        let size_len = if self.major_len > 0 {
+            self.size_len.max(self.major_len + 2 + self.minor_len)
+        } else {
+            self.size_len
+        };
+        let column = |shown: bool, len: usize| if shown { len + 1 } else { 0 };
+        column(app.print_inode, self.inode_len)
+            + column(app.display_size_in_blocks, self.blocks_len)
+            + 10
+            + usize::from(self.mode_indicators)
+            + column(true, self.links_len)
+            + column(app.print_owner, self.owner_len)
+            + column(app.print_group, self.group_len)
+            + column(app.print_context, self.context_len)
+            + column(self.capabilities_len > 0, self.capabilities_len)
+            + column(true, size_len)
+            + column(true, time_len)
+            + 1
+            + column(git_status, 2)
+    }
 
-    let mut buf = Buffer::new();
-    largest_size = buf.format(largest_size as u64).len();
-    largest_links = buf.format(largest_links as u64).len();
-    inode_len = buf.format(inode_len as u64).len();
-    blocks_len = buf.format(blocks_len as u64).len();
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            owner_len: self.owner_len.max(other.owner_len),
+            group_len: self.group_len.max(other.group_len),
+            size_len: self.size_len.max(other.size_len),
+            major_len: self.major_len.max(other.major_len),
+            minor_len: self.minor_len.max(other.minor_len),
+            links_len: self.links_len.max(other.links_len),
+            inode_len: self.inode_len.max(other.inode_len),
+            blocks_len: self.blocks_len.max(other.blocks_len),
+            context_len: self.context_len.max(other.context_len),
+            capabilities_len: self.capabilities_len.max(other.capabilities_len),
+            mode_indicators: self.mode_indicators || other.mode_indicators,
+            quote_padding: self.quote_padding || other.quote_padding,
+            current_time: self.current_time,
+        }
+    }
 
-    let current_time = syscalls::gettimeofday().unwrap().tv_sec;
-    let one_year = 365 * 24 * 60 * 60;
+    /// Prints one entry, with the tree guides if there are any just before the name
+    pub fn write_row(
+        &self,
+        direntry: &(DirEntry, Option<Status>),
+        dir: &Directory,
+        app: &mut App,
+        guides: &[u8],
+        guides_below: &[u8],
+    ) {
+        use Style::*;
+
+        let size_len = if self.major_len > 0 {
+            self.size_len.max(self.major_len + 2 + self.minor_len)
+        } else {
+            self.size_len
+        };
+        let one_year = 365 * 24 * 60 * 60;
 
-    for direntry in entries {
         let e = &direntry.0;
         let status = direntry.1.clone().unwrap_or_default();
         let mode = status.mode;
@@ -82,14 +213,18 @@ pub fn write_details(entries: &[(DirEntry, Option<Status>)], dir: &Directory, ap
         if app.print_inode {
             app.out
                 .style(Magenta)
-                .align_right(status.inode, inode_len)
+                .align_right(status.inode, self.inode_len)
                 .push(b' ');
         }
 
         if app.display_size_in_blocks {
             app.out
                 .style(White)
-                .align_right(status.blocks as u64, blocks_len)
+                .align_right_size(
+                    status.blocks as u64 * BLOCK_BYTES,
+                    app.block_size,
+                    self.blocks_len,
+                )
                 .push(b' ');
         }
 
@@ -98,25 +233,34 @@ pub fn write_details(entries: &[(DirEntry, Option<Status>)], dir: &Directory, ap
             match mode & libc::S_IFMT {
                 libc::S_IFDIR => (BlueBold, "d"),
                 libc::S_IFLNK => (Cyan, "l"),
+                libc::S_IFBLK => (YellowBold, "b"),
+                libc::S_IFCHR => (YellowBold, "c"),
+                libc::S_IFIFO => (YellowBold, "p"),
+                libc::S_IFSOCK => (MagentaBold, "s"),
                 _ => (White, "-"),
             }
         );
 
-        print_rwx(app, mode, S_IRUSR, S_IWUSR, S_IXUSR);
-        print_rwx(app, mode, S_IRGRP, S_IWGRP, S_IXGRP);
-        print_rwx(app, mode, S_IROTH, S_IWOTH, S_IXOTH);
+        print_rwx(app, mode, [S_IRUSR, S_IWUSR, S_IXUSR], S_ISUID, b's');
+        print_rwx(app, mode, [S_IRGRP, S_IWGRP, S_IXGRP], S_ISGID, b's');
+        print_rwx(app, mode, [S_IROTH, S_IWOTH, S_IXOTH], S_ISVTX, b't');
+        if self.mode_indicators {
+            app.out
+                .style(White)
+                .push(status.mode_indicator.unwrap_or(b' '));
+        }
 
         app.out
             .push(b' ')
             .style(White)
-            .align_right(status.links, largest_links);
+            .align_right(status.links, self.links_len);
 
         if app.print_owner {
             let name = app.getpwuid(status.uid);
             app.out
                 .push(b' ')
                 .style(YellowBold)
-                .align_left(name, longest_name_len);
+                .align_left(name, self.owner_len);
         }
 
         if app.print_group {
@@ -124,33 +268,69 @@ pub fn write_details(entries: &[(DirEntry, Option<Status>)], dir: &Directory, ap
             app.out
                 .push(b' ')
                 .style(YellowBold)
-                .align_left(group, longest_group_len);
+                .align_left(group, self.group_len);
         }
 
-        app.out
-            .push(b' ')
-            .style(GreenBold)
-            .align_right(status.size as u64, largest_size);
-
-        let localtime = app.convert_to_localtime(status.time);
-
-        print!(app, " ", Blue, month_abbr(localtime.month), " ");
+        if app.print_context {
+            app.out
+                .push(b' ')
+                .style(White)
+                .align_left(direntry.context(), self.context_len);
+        }
 
-        let day = localtime.day_of_month;
-        print!(app, (day < 10).map(" "), day, " ");
+        if self.capabilities_len > 0 {
+            let capabilities = status.capabilities.as_deref().unwrap_or_default();
+            app.out
+                .push(b' ')
+                .style(Red)
+                .align_left(capabilities, self.capabilities_len);
+        }
 
-        if current_time - status.time < one_year / 2 {
-            let hour = localtime.hour;
-            print!(app, (hour < 10).map("0"), hour, ":");
+        app.out.push(b' ').style(GreenBold);
+        if status.is_device() {
+            app.out
+                .align_right(
+                    libc::major(status.rdev).into(),
+                    size_len - 2 - self.minor_len,
+                )
+                .push(b',')
+                .push(b' ')
+                .align_right(libc::minor(status.rdev).into(), self.minor_len);
+        } else {
+            app.out
+                .align_right_size(status.size as u64, app.file_block_size, size_len);
+        }
 
-            let minute = localtime.minute;
-            print!(app, (minute < 10).map("0"), minute);
+        app.out.push(b' ').style(Blue);
+        let mut time_len = 0;
+        if let Some((time, time_nsec)) = status.time {
+            let localtime = app.convert_to_localtime(time, time_nsec);
+            let format = if self.current_time - time < one_year / 2 {
+                app.time_style.recent
+            } else {
+                app.time_style.old
+            };
+            if guides_below.is_empty() {
+                localtime.format(format, &mut app.out);
+            } else {
+                let mut formatted = Vec::new();
+                localtime.format(format, &mut formatted);
+                app.out.write(&formatted);
+                time_len = formatted.len();
+            }
         } else {
-            print!(app, " ", localtime.year + 1900);
+            // Like GNU ls, a missing birth time is a ? lined up with the times around it
+            time_len = self.time_width(app);
+            app.out.align_right_bytes(b"?", time_len);
         }
 
         app.out.push(b' ');
 
+        let git_status = crate::git::status(app, direntry);
+        if let Some(status) = git_status {
+            print!(app, status, " ");
+        }
+
         let (mut style, suffix) = direntry.style(dir, app);
         // FIXME: This is a hack to get red-colored broken symlinks in -l output.
         // This logic is at completely the wrong place, and it's setting the style to RedBold, not
@@ -159,38 +339,147 @@ pub fn write_details(entries: &[(DirEntry, Option<Status>)], dir: &Directory, ap
             && app.color == crate::cli::Color::Always
             && syscalls::faccessat(dir.raw_fd(), e.name, libc::F_OK).is_err()
         {
-            style = RedBold;
+            style = app.ls_colors.type_style(b"or").unwrap_or(RedBold);
+        }
+        if !guides.is_empty() {
+            app.out.style(Gray).write(guides);
         }
-        print!(app, style, e.name, suffix.map(|s| (White, s)));
+        app.out.style(style);
+        if self.quote_padding && !app.out.quotes_name(e.name.as_bytes()) {
+            app.out.push(b' ');
+        }
+        app.out.write_entry_name(e.name.as_bytes());
+        print!(app, suffix.map(|s| (White, s)));
 
         if (mode & libc::S_IFMT) == libc::S_IFLNK {
             let mut buf = [0u8; 1024];
             if let Ok(linked_to) = syscalls::readlinkat(dir.raw_fd(), e.name, &mut buf) {
-                print!(app, Gray, " -> ", White, linked_to);
+                print!(app, Gray, " -> ", White);
+                app.out.write_name(linked_to);
             }
         }
 
         print!(app, Reset, "\n");
+
+        if let Some(listing) = app.xattrs {
+            // In a tree, the guides below an entry line up with the ones before its name
+            let indent = if guides_below.is_empty() {
+                0
+            } else {
+                self.name_column(app, time_len, git_status.is_some())
+            };
+            let names = status.xattrs.as_deref();
+            write_xattrs(e, names, dir, listing, (indent, guides_below), app);
+        }
+    }
+}
+
+/// Lists extended attributes under an entry like macOS ls -l@ does, one per line with its size
+/// and maybe its value. In a tree, each line has the guides that go past the entry, indented to
+/// where they are in the entry's row.
+fn write_xattrs(
+    e: &DirEntry,
+    names: Option<&[u8]>,
+    dir: &Directory,
+    listing: XattrListing,
+    (indent, guides): (usize, &[u8]),
+    app: &mut App,
+) {
+    use Style::*;
+
+    let follow_symlinks = app.follow_symlinks == FollowSymlinks::Always;
+    let Some(names) = names else {
+        return;
+    };
+    for attribute in names.split_inclusive(|b| *b == 0) {
+        let value = match listing {
+            XattrListing::Names => None,
+            XattrListing::Values => xattr::get(dir.raw_fd(), e.name, attribute, follow_symlinks),
+        };
+        let size = match &value {
+            Some(value) => Some(value.len()),
+            None => xattr::size(dir.raw_fd(), e.name, attribute, follow_symlinks),
+        };
+        let name = &attribute[..attribute.len() - 1];
+        for _ in 0..indent {
+            app.out.push(b' ');
+        }
+        app.out
+            .style(Gray)
+            .write(guides)
+            .push(b'\t')
+            .style(White)
+            .write_name(name)
+            .push(b'\t');
+        match size {
+            Some(size) => app.out.style(GreenBold).align_right(size as u64, 4),
+            None => app.out.style(GreenBold).align_right_bytes(b"?", 4),
+        };
+        if let Some(value) = value {
+            app.out.push(b' ').style(Reset);
+            write_xattr_value(&value, &mut app.out);
+        }
+        print!(app, Reset, "\n");
     }
 }
 
+/// Values are often text but can be anything. Text is written as it is, and anything else as hex
+/// like getfattr -e hex does, so that binary values like security.capability can still be read.
+fn write_xattr_value(value: &[u8], out: &mut OutputBuffer) {
+    // Text values are usually null-terminated
+    let text = value.strip_suffix(b"\0").unwrap_or(value);
+    if text.iter().all(|b| (b' '..=b'~').contains(b)) {
+        out.write(text);
+        return;
+    }
+    let hex = b"0123456789abcdef";
+    out.write(b"0x");
+    for b in value {
+        out.push(hex[*b as usize >> 4]).push(hex[*b as usize & 0xf]);
+    }
+}
+
+// st_blocks is always in units of 512 bytes, regardless of the filesystem's block size
+const BLOCK_BYTES: u64 = 512;
+
 fn print_total_blocks(entries: &[(DirEntry, Option<Status>)], app: &mut App) {
+    let blocks = entries.iter().map(DirEntryExt::blocks).sum::<u64>();
     print!(
         app,
         "total ",
-        entries
-            .iter()
-            .filter_map(|(_, s)| s.as_ref())
-            .map(|status| status.blocks)
-            .sum::<i64>() as u64,
+        Buffer::new().format_size(blocks * BLOCK_BYTES, app.block_size),
         "\n"
     );
 }
 
+fn context_width(entries: &[(DirEntry, Option<Status>)]) -> usize {
+    entries.iter().map(|e| e.context().len()).max().unwrap_or(0)
+}
+
+fn blocks_width(entries: &[(DirEntry, Option<Status>)], app: &App) -> usize {
+    let mut buf = Buffer::new();
+    entries
+        .iter()
+        .map(|e| {
+            buf.format_size(e.blocks() * BLOCK_BYTES, app.block_size)
+                .len()
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// If any name has to be quoted, GNU ls puts a space before the others so that they still line up
+fn needs_quote_padding(entries: &[(DirEntry, Option<Status>)], app: &App) -> bool {
+    entries
+        .iter()
+        .any(|e| app.out.quotes_name(e.name().as_bytes()))
+}
+
 pub struct LayoutCursor {
     column: usize,
     left_in_this_column: usize,
     rows: usize,
+    columns: usize,
     this_layout_width: usize,
 }
 
@@ -200,6 +489,7 @@ pub fn write_grid(
     app: &mut App,
     terminal_width: usize,
 ) {
+    use crate::cli::SortDirection;
     use Style::*;
 
     if app.display_size_in_blocks {
@@ -218,13 +508,13 @@ pub fn write_grid(
     };
 
     let blocks_len = if app.display_size_in_blocks {
-        let blocks = entries
-            .iter()
-            .filter_map(|(_, s)| s.as_ref())
-            .map(|status| status.blocks)
-            .max()
-            .unwrap_or(0);
-        Buffer::new().format(blocks as u64).len()
+        blocks_width(entries, app)
+    } else {
+        0
+    };
+
+    let context_len = if app.print_context {
+        context_width(entries)
     } else {
         0
     };
@@ -234,11 +524,16 @@ pub fn write_grid(
     // we keep a Vec of column widths (widest name in each column) for every number of columns, and
     // when we add an entry to a column which makes the sum of all columns for that layout too
     // large, we discard it.
+    // With -x the entries fill each row before moving to the next, so instead of counting down the
+    // rows in a column, each layout just cycles through its columns.
+
+    let quote_padding = needs_quote_padding(entries, app);
 
     let sum_to = |a| a * (a + 1) / 2;
 
     let mut lengths = Vec::with_capacity(entries.len());
     let mut styles = Vec::with_capacity(entries.len());
+    let mut git_statuses = Vec::new();
 
     let max_possible_columns = core::cmp::min(terminal_width / 3, entries.len());
 
@@ -253,6 +548,7 @@ pub fn write_grid(
             column: 0,
             left_in_this_column: rows,
             rows,
+            columns: i,
             this_layout_width: (i - 1) * 2, // Initially, just the 2 spaces between each column
         });
         if rows == 1 {
@@ -262,8 +558,20 @@ pub fn write_grid(
 
     for entry in entries {
         let style = entry.style(dir, app);
-        let len =
-            len_utf8(entry.name().as_bytes()) + style.1.is_some() as usize + inode_len + blocks_len;
+        let git_status = crate::git::status(app, entry);
+        git_statuses.extend(git_status);
+        let name = entry.name().as_bytes();
+        // The inode, block, context and git columns are each followed by a space
+        let len = app.out.name_len(name)
+            + git_status.map_or(0, |_| 3)
+            + (quote_padding && !app.out.quotes_name(name)) as usize
+            + style.1.is_some() as usize
+            + inode_len
+            + app.print_inode as usize
+            + blocks_len
+            + app.display_size_in_blocks as usize
+            + context_len
+            + app.print_context as usize;
         lengths.push(len);
         styles.push(style);
 
@@ -278,38 +586,61 @@ pub fn write_grid(
 
             if cursors[i].this_layout_width > terminal_width {
                 cursors.pop();
-            } else {
+            } else if app.grid_sort_direction == SortDirection::Vertical {
                 cursors[i].left_in_this_column -= 1;
                 if cursors[i].left_in_this_column == 0 {
                     cursors[i].column += 1;
                     cursors[i].left_in_this_column = cursors[i].rows;
                 }
+            } else {
+                cursors[i].column += 1;
+                if cursors[i].column == cursors[i].columns {
+                    cursors[i].column = 0;
+                }
             }
         }
     }
 
-    let rows = cursors.last().map(|c| c.rows).unwrap_or(entries.len());
+    let (rows, columns) = cursors
+        .last()
+        .map(|c| (c.rows, c.columns))
+        .unwrap_or((entries.len(), 1));
 
     let mut widths = Vec::new();
-    widths.extend(
-        lengths
-            .chunks(rows)
-            .map(|column| column.iter().max().copied().unwrap_or(1) + 2),
-    );
+    if app.grid_sort_direction == SortDirection::Vertical {
+        widths.extend(
+            lengths
+                .chunks(rows)
+                .map(|column| column.iter().max().copied().unwrap_or(1) + 2),
+        );
+    } else {
+        widths.extend((0..columns).map(|c| {
+            lengths
+                .iter()
+                .skip(c)
+                .step_by(columns)
+                .max()
+                .copied()
+                .unwrap_or(1)
+                + 2
+        }));
+    }
     if let Some(width) = widths.last_mut() {
         *width -= 2;
     }
 
     for r in 0..rows {
         for (c, width) in widths.iter().enumerate() {
-            let (e, name_len, (style, suffix)) = match (
-                entries.get(c * rows + r),
-                lengths.get(c * rows + r),
-                styles.get(c * rows + r),
-            ) {
-                (Some(e), Some(name_len), Some(style)) => (e, name_len, style),
-                _ => continue,
+            let index = if app.grid_sort_direction == SortDirection::Vertical {
+                c * rows + r
+            } else {
+                r * columns + c
             };
+            let (e, name_len, (style, suffix)) =
+                match (entries.get(index), lengths.get(index), styles.get(index)) {
+                    (Some(e), Some(name_len), Some(style)) => (e, name_len, style),
+                    _ => continue,
+                };
 
             if app.print_inode {
                 app.out
@@ -321,15 +652,30 @@ pub fn write_grid(
             if app.display_size_in_blocks {
                 app.out
                     .style(White)
-                    .align_right(e.blocks(), blocks_len)
+                    .align_right_size(e.blocks() * BLOCK_BYTES, app.block_size, blocks_len)
+                    .push(b' ');
+            }
+
+            if app.print_context {
+                app.out
+                    .style(White)
+                    .align_right_bytes(e.context(), context_len)
                     .push(b' ');
             }
 
+            if let Some(status) = git_statuses.get(index) {
+                print!(app, *status, " ");
+            }
+
             app.out.style(*style);
-            print!(app, e.name(), suffix.map(|s| (White, s)));
+            if quote_padding && !app.out.quotes_name(e.name().as_bytes()) {
+                app.out.push(b' ');
+            }
+            app.out.write_entry_name(e.name().as_bytes());
+            print!(app, suffix.map(|s| (White, s)));
 
             // don't pad out spaces from here until the newline
-            if c + 1 == widths.len() {
+            if c + 1 == widths.len() || index + 1 == entries.len() {
                 break;
             }
 
@@ -348,29 +694,32 @@ pub fn write_stream(entries: &[(DirEntry, Option<Status>)], dir: &Directory, app
         print_total_blocks(entries, app);
     }
 
-    for e in entries.iter().take(entries.len() - 1) {
+    for (i, e) in entries.iter().enumerate() {
         if app.print_inode {
             print!(app, Style::Magenta, e.inode(), " ");
         }
 
         if app.display_size_in_blocks {
-            print!(app, Style::White, e.blocks(), " ");
+            print!(
+                app,
+                Style::White,
+                Buffer::new().format_size(e.blocks() * BLOCK_BYTES, app.block_size),
+                " "
+            );
+        }
+
+        if app.print_context {
+            print!(app, Style::White, e.context(), " ");
         }
 
         let (style, suffix) = e.style(dir, app);
-        print!(
-            app,
-            style,
-            e.name(),
-            suffix.map(|s| (Style::White, s)),
-            Style::White,
-            ", "
-        );
-    }
-    if let Some(e) = entries.last() {
-        app.out.write(e.name().as_bytes());
+        app.out.style(style).write_entry_name(e.name().as_bytes());
+        print!(app, suffix.map(|s| (Style::White, s)));
+        if i + 1 < entries.len() {
+            print!(app, Style::White, ", ");
+        }
     }
-    app.out.push(b'\n');
+    print!(app, Style::Reset, "\n");
 }
 
 pub fn write_single_column(entries: &[(DirEntry, Option<Status>)], dir: &Directory, app: &mut App) {
@@ -386,8 +735,13 @@ pub fn write_single_column(entries: &[(DirEntry, Option<Status>)], dir: &Directo
     };
 
     let blocks_len = if app.display_size_in_blocks {
-        let blocks = entries.iter().map(DirEntryExt::blocks).max().unwrap_or(0);
-        Buffer::new().format(blocks).len()
+        blocks_width(entries, app)
+    } else {
+        0
+    };
+
+    let context_len = if app.print_context {
+        context_width(entries)
     } else {
         0
     };
@@ -403,19 +757,24 @@ pub fn write_single_column(entries: &[(DirEntry, Option<Status>)], dir: &Directo
         if app.display_size_in_blocks {
             app.out
                 .style(Style::White)
-                .align_right(e.blocks(), blocks_len)
+                .align_right_size(e.blocks() * BLOCK_BYTES, app.block_size, blocks_len)
                 .push(b' ');
         }
 
+        if app.print_context {
+            app.out
+                .style(Style::White)
+                .align_right_bytes(e.context(), context_len)
+                .push(b' ');
+        }
+
+        if let Some(status) = crate::git::status(app, e) {
+            print!(app, status, " ");
+        }
+
         let (style, suffix) = e.style(dir, app);
-        print!(
-            app,
-            style,
-            e.name(),
-            suffix.map(|s| (Style::White, s)),
-            Style::Reset,
-            "\n"
-        );
+        app.out.style(style).write_entry_name(e.name().as_bytes());
+        print!(app, suffix.map(|s| (Style::White, s)), Style::Reset, "\n");
     }
 }
 
@@ -454,7 +813,7 @@ impl Writable for &str {
 
 impl Writable for CStr<'_> {
     fn write(&self, out: &mut OutputBuffer) {
-        out.write(self.as_bytes());
+        out.write_name(self.as_bytes());
     }
 }
 
@@ -531,6 +890,9 @@ pub struct OutputBuffer {
     style: Style,
     fd: i32,
     pub color: bool,
+    pub replace_unprintable_bytes: bool,
+    pub quoting_style: QuotingStyle,
+    pub hyperlinks: Option<Hyperlinks>,
 }
 
 impl OutputBuffer {
@@ -542,6 +904,9 @@ impl OutputBuffer {
             buf_used: 0,
             style: Style::Reset,
             color: true,
+            replace_unprintable_bytes: false,
+            quoting_style: QuotingStyle::Literal,
+            hyperlinks: None,
             fd,
         }
     }
@@ -580,6 +945,112 @@ impl OutputBuffer {
         self
     }
 
+    /// Write a file name, replacing control characters and invalid UTF-8 with '?' if -q was
+    /// passed. Those bytes would otherwise be interpreted by the terminal. Names are quoted
+    /// according to the quoting style.
+    pub fn write_name(&mut self, name: &[u8]) -> &mut Self {
+        if self.quoting_style != QuotingStyle::Literal {
+            quote_name(
+                name,
+                self.quoting_style,
+                self.replace_unprintable_bytes,
+                self,
+            );
+            return self;
+        }
+        if !self.replace_unprintable_bytes || name.iter().all(|b| (b' '..=b'~').contains(b)) {
+            return self.write(name);
+        }
+        for chunk in name.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                if c.is_control() {
+                    self.push(b'?');
+                } else {
+                    self.write(c.encode_utf8(&mut [0u8; 4]).as_bytes());
+                }
+            }
+            for _ in chunk.invalid() {
+                self.push(b'?');
+            }
+        }
+        self
+    }
+
+    /// Write the name of an entry in a listing, which is a link to the file with --hyperlink.
+    /// The escape sequences around it take up no space in the terminal.
+    pub fn write_entry_name(&mut self, name: &[u8]) -> &mut Self {
+        let Some(mut links) = self.hyperlinks.take() else {
+            return self.write_name(name);
+        };
+        links.target.clear();
+        if !name.starts_with(b"/") {
+            links.target.extend_from_slice(&links.dir);
+        }
+        push_path_components(&mut links.target, name);
+        if links.target.is_empty() {
+            links.target.push(b'/');
+        }
+        // Terminated by BEL rather than ST, like GNU ls
+        self.write(b"\x1b]8;;file://").write(&links.hostname);
+        self.write_percent_encoded(&links.target).push(0x07);
+        self.write_name(name).write(b"\x1b]8;;\x07");
+        self.hyperlinks = Some(links);
+        self
+    }
+
+    fn write_percent_encoded(&mut self, path: &[u8]) -> &mut Self {
+        const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+        for &b in path {
+            if b.is_ascii_alphanumeric() || b"-_.~/".contains(&b) {
+                self.push(b);
+            } else {
+                self.push(b'%')
+                    .push(HEX_DIGITS[usize::from(b >> 4)])
+                    .push(HEX_DIGITS[usize::from(b & 0xf)]);
+            }
+        }
+        self
+    }
+
+    /// The number of terminal columns that write_name will use for this name
+    pub fn name_len(&self, name: &[u8]) -> usize {
+        if self.quoting_style != QuotingStyle::Literal {
+            let mut width = NameWidth(0);
+            quote_name(
+                name,
+                self.quoting_style,
+                self.replace_unprintable_bytes,
+                &mut width,
+            );
+            return width.0;
+        }
+        if !self.replace_unprintable_bytes {
+            return len_utf8(name);
+        }
+        let mut len = 0;
+        for chunk in name.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                len += if c.is_control() {
+                    1
+                } else {
+                    c.width().unwrap_or(0)
+                };
+            }
+            len += chunk.invalid().len();
+        }
+        len
+    }
+
+    /// Whether this name is quoted by a style that only quotes names when they need it
+    pub fn quotes_name(&self, name: &[u8]) -> bool {
+        let escapes = match self.quoting_style {
+            QuotingStyle::Shell => false,
+            QuotingStyle::ShellEscape => true,
+            _ => return false,
+        };
+        shell_quotes(name, false, escapes) != ShellQuotes::None
+    }
+
     pub fn style(&mut self, style: Style) -> &mut Self {
         if !self.color {
             return self;
@@ -602,8 +1073,19 @@ impl OutputBuffer {
     }
 
     pub fn align_right(&mut self, value: u64, width: usize) -> &mut Self {
-        let mut buf = Buffer::new();
-        let formatted = buf.format(value);
+        self.align_right_bytes(Buffer::new().format(value), width)
+    }
+
+    pub fn align_right_size(
+        &mut self,
+        bytes: u64,
+        block_size: BlockSize,
+        width: usize,
+    ) -> &mut Self {
+        self.align_right_bytes(Buffer::new().format_size(bytes, block_size), width)
+    }
+
+    pub fn align_right_bytes(&mut self, formatted: &[u8], width: usize) -> &mut Self {
         if formatted.len() < width {
             for _ in 0..width - formatted.len() {
                 self.push(b' ');
@@ -614,6 +1096,256 @@ impl OutputBuffer {
     }
 }
 
+/// With --hyperlink, names link to file://HOST/PATH using the OSC 8 escape sequence
+pub struct Hyperlinks {
+    hostname: Vec<u8>,
+    cwd: Vec<u8>,
+    /// The absolute path of the directory whose entries are being printed, without a trailing /
+    dir: Vec<u8>,
+    /// Where the link currently being written goes
+    target: Vec<u8>,
+}
+
+impl Hyperlinks {
+    pub fn new() -> Option<Self> {
+        let uname = syscalls::uname().ok()?;
+        let hostname = uname
+            .nodename
+            .iter()
+            .take_while(|c| **c != 0)
+            .map(|c| *c as u8)
+            .collect();
+        let mut buf = [0u8; libc::PATH_MAX as usize];
+        let cwd = syscalls::getcwd(&mut buf).ok()?;
+        let cwd = cwd.strip_suffix(b"/").unwrap_or(cwd).to_vec();
+        Some(Self {
+            hostname,
+            dir: cwd.clone(),
+            cwd,
+            target: Vec::new(),
+        })
+    }
+
+    /// Point links at a directory, which may be relative to the current directory
+    pub fn set_dir(&mut self, path: &[u8]) {
+        self.dir.clear();
+        if !path.starts_with(b"/") {
+            self.dir.extend_from_slice(&self.cwd);
+        }
+        push_path_components(&mut self.dir, path);
+    }
+}
+
+/// Appends a path to an absolute one, resolving . and .. without looking at the filesystem
+pub fn push_path_components(absolute: &mut Vec<u8>, path: &[u8]) {
+    for component in path.split(|b| *b == b'/' || *b == 0) {
+        match component {
+            b"" | b"." => {}
+            b".." => {
+                let parent = absolute.iter().rposition(|b| *b == b'/').unwrap_or(0);
+                absolute.truncate(parent);
+            }
+            _ => {
+                absolute.push(b'/');
+                absolute.extend_from_slice(component);
+            }
+        }
+    }
+}
+
+/// Receives a name as it is quoted, so the same code can both print names and measure them
+trait NameSink {
+    /// Quotes and escape sequences, or bytes copied from the name as-is
+    fn bytes(&mut self, bytes: &[u8]);
+    fn char(&mut self, c: char);
+}
+
+impl NameSink for OutputBuffer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.write(bytes);
+    }
+
+    fn char(&mut self, c: char) {
+        self.write(c.encode_utf8(&mut [0u8; 4]).as_bytes());
+    }
+}
+
+struct NameWidth(usize);
+
+impl NameSink for NameWidth {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0 += bytes.len();
+    }
+
+    fn char(&mut self, c: char) {
+        self.0 += c.width().unwrap_or(0);
+    }
+}
+
+/// The characters of a name, with each byte that isn't valid UTF-8 as an error
+fn decode_name(name: &[u8]) -> impl Iterator<Item = Result<char, u8>> + '_ {
+    name.utf8_chunks().flat_map(|chunk| {
+        let invalid = chunk.invalid().iter().map(|b| Err(*b));
+        chunk.valid().chars().map(Ok).chain(invalid)
+    })
+}
+
+fn is_printable(c: Result<char, u8>) -> bool {
+    matches!(c, Ok(c) if !c.is_control())
+}
+
+/// Quotes a name the way GNU's quotearg does for ls in a UTF-8 locale. The literal style is
+/// handled by the callers.
+fn quote_name(
+    name: &[u8],
+    style: QuotingStyle,
+    replace_unprintable_bytes: bool,
+    out: &mut impl NameSink,
+) {
+    use QuotingStyle::*;
+    let (always, escapes) = match style {
+        Literal | Shell => (false, false),
+        ShellAlways => (true, false),
+        ShellEscape => (false, true),
+        ShellEscapeAlways => (true, true),
+        C | Escape | Locale => {
+            let quotes = match style {
+                C => Some(('"', '"')),
+                Locale => Some(('\u{2018}', '\u{2019}')),
+                _ => None,
+            };
+            if let Some((open, _)) = quotes {
+                out.char(open);
+            }
+            for c in decode_name(name) {
+                match c {
+                    Ok('\\') => out.bytes(b"\\\\"),
+                    Ok('"') if style == C => out.bytes(b"\\\""),
+                    Ok(' ') if style == Escape => out.bytes(b"\\ "),
+                    // Other spaces like U+00A0 look just like ' ', so -b shows their bytes
+                    Ok(c) if style == Escape && c.is_whitespace() => write_escapes(Ok(c), out),
+                    Ok(c) if is_printable(Ok(c)) => out.char(c),
+                    c => write_escapes(c, out),
+                }
+            }
+            if let Some((_, close)) = quotes {
+                out.char(close);
+            }
+            return;
+        }
+    };
+
+    let write_as_is = |c: Result<char, u8>, out: &mut _| match c {
+        Ok(c) if is_printable(Ok(c)) => NameSink::char(out, c),
+        _ if replace_unprintable_bytes => NameSink::bytes(out, b"?"),
+        Ok(c) => NameSink::char(out, c),
+        Err(b) => NameSink::bytes(out, &[b]),
+    };
+
+    match shell_quotes(name, always, escapes) {
+        ShellQuotes::None => decode_name(name).for_each(|c| write_as_is(c, out)),
+        ShellQuotes::Double => {
+            out.bytes(b"\"");
+            decode_name(name).for_each(|c| write_as_is(c, out));
+            out.bytes(b"\"");
+        }
+        ShellQuotes::Single => {
+            out.bytes(b"'");
+            // Unprintable characters go in $'' strings, which bash and zsh expand escapes in
+            let mut in_escapes = false;
+            for c in decode_name(name) {
+                if escapes && !is_printable(c) {
+                    if !in_escapes {
+                        out.bytes(b"'$'");
+                        in_escapes = true;
+                    }
+                    write_escapes(c, out);
+                    continue;
+                }
+                if in_escapes {
+                    out.bytes(b"''");
+                    in_escapes = false;
+                }
+                if c == Ok('\'') {
+                    out.bytes(b"'\\''");
+                } else {
+                    write_as_is(c, out);
+                }
+            }
+            out.bytes(b"'");
+        }
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum ShellQuotes {
+    None,
+    Double,
+    Single,
+}
+
+fn shell_quotes(name: &[u8], always: bool, escapes: bool) -> ShellQuotes {
+    let mut needs_quotes = always || name.is_empty();
+    let mut has_single_quote = false;
+    // Names with a ' are put in double quotes instead, if nothing else in them is special there
+    let mut double_quotable = true;
+    for (i, c) in decode_name(name).enumerate() {
+        let (special, double_quotable_char) = match c {
+            Ok(' ') => (true, true),
+            Ok('\'') => {
+                has_single_quote = true;
+                (true, true)
+            }
+            // Only special at the start of a word, and braces only on their own
+            Ok('#' | '~') => (i == 0, i == 0),
+            Ok('{' | '}') => (name.len() == 1, name.len() == 1),
+            Ok('!' | '"' | '$' | '&' | '(' | ')' | '*' | ';' | '<' | '=' | '>' | '?' | '[')
+            | Ok('\\' | '^' | '`' | '|') => (true, false),
+            // Characters with C escapes like \n
+            Ok('\u{7}'..='\r') => (true, false),
+            c if is_printable(c) => (false, true),
+            _ => (escapes, false),
+        };
+        needs_quotes |= special;
+        double_quotable &= double_quotable_char;
+    }
+    if !needs_quotes {
+        ShellQuotes::None
+    } else if has_single_quote && double_quotable {
+        ShellQuotes::Double
+    } else {
+        ShellQuotes::Single
+    }
+}
+
+/// Writes a C escape for each byte of an unprintable character, like \n or \302\237
+fn write_escapes(c: Result<char, u8>, out: &mut impl NameSink) {
+    let mut buf = [0u8; 4];
+    let len = match c {
+        Ok(c) => c.encode_utf8(&mut buf).len(),
+        Err(b) => {
+            buf[0] = b;
+            1
+        }
+    };
+    for &b in &buf[..len] {
+        let letter = match b {
+            0x07 => b'a',
+            0x08 => b'b',
+            b'\t' => b't',
+            b'\n' => b'n',
+            0x0b => b'v',
+            0x0c => b'f',
+            b'\r' => b'r',
+            _ => {
+                out.bytes(&[b'\\', b'0' + (b >> 6), b'0' + (b >> 3 & 7), b'0' + (b & 7)]);
+                continue;
+            }
+        };
+        out.bytes(&[b'\\', letter]);
+    }
+}
+
 impl core::fmt::Write for OutputBuffer {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
         self.write(s.as_bytes());
@@ -642,14 +1374,6 @@ fn write_all(bytes: &[u8], fd: i32) {
     }
 }
 
-static MONTH_NAMES: &[&[u8]] = &[
-    b"Jan", b"Feb", b"Mar", b"Apr", b"May", b"Jun", b"Jul", b"Aug", b"Sep", b"Oct", b"Nov", b"Dec",
-];
-
-fn month_abbr(month: c_int) -> &'static [u8] {
-    MONTH_NAMES.get(month as usize).copied().unwrap_or(b"???")
-}
-
 // This code was translated almost directly from the implementation in GNU ls
 //
 pub fn vercmp(s1_cstr: CStr, s2_cstr: CStr) -> core::cmp::Ordering {
@@ -701,27 +1425,3 @@ pub fn vercmp(s1_cstr: CStr, s2_cstr: CStr) -> core::cmp::Ordering {
     }
     Ordering::Equal
 }
-
-trait SliceExt {
-    fn digit_at(&self, index: usize) -> bool;
-}
-
-impl SliceExt for &[u8] {
-    fn digit_at(&self, index: usize) -> bool {
-        self.get(index).map(u8::is_ascii_digit).unwrap_or(false)
-    }
-}
-
-trait BoolExt {
-    fn map<T>(self, value: T) -> Option<T>;
-}
-
-impl BoolExt for bool {
-    fn map<T>(self, value: T) -> Option<T> {
-        if self {
-            Some(value)
-        } else {
-            None
-        }
-    }
-}
@@ -1,4 +1,8 @@
-use crate::output::{OutputBuffer, Writable};
+use crate::{
+    output::{OutputBuffer, Writable},
+    utils::memcmp_ignore_case,
+};
+use alloc::vec::Vec;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Style {
@@ -16,6 +20,10 @@ pub enum Style {
     MagentaBold,
     CyanBold,
     Fixed(u8),
+    Dim,
+    BlackOnRed,
+    /// An SGR sequence from LS_COLORS, such as 01;34
+    Custom(&'static [u8]),
 }
 
 impl Style {
@@ -36,13 +44,102 @@ impl Style {
             BlueBold => &b"\x1B[1;34m"[..],
             MagentaBold => &b"\x1B[1;35m"[..],
             CyanBold => &b"\x1B[1;36m"[..],
+            Dim => &b"\x1B[0;2m"[..],
+            BlackOnRed => &b"\x1B[0;30;41m"[..],
             Fixed(c) => {
                 out.write(&b"\x1B[0;38;5;"[..]);
                 u64::from(c).write(out);
                 out.push(b'm');
                 return;
             }
+            Custom(sgr) => {
+                out.write(&b"\x1B[0;"[..]).write(sgr).push(b'm');
+                return;
+            }
         };
         out.write(bytes);
     }
 }
+
+/// Styles parsed from the LS_COLORS environment variable, which take precedence over the styles
+/// that are compiled in.
+pub struct LsColors {
+    /// Two-letter keys for file types, like di or ln
+    types: Vec<(&'static [u8], Style)>,
+    /// *.ext rules, sorted case-insensitively so they can be binary searched
+    extensions: Vec<(&'static [u8], Style)>,
+    /// Every other * rule, such as *~ or *.tar.gz, which are checked in order
+    suffixes: Vec<(&'static [u8], Style)>,
+}
+
+impl LsColors {
+    pub const EMPTY: Self = Self {
+        types: Vec::new(),
+        extensions: Vec::new(),
+        suffixes: Vec::new(),
+    };
+
+    #[inline(never)]
+    pub fn parse(spec: &'static [u8]) -> Self {
+        let mut colors = Self::EMPTY;
+        // Later rules override earlier ones, so we collect them back to front and keep the first
+        for rule in spec.rsplit(|b| *b == b':') {
+            let Some(eq) = rule.iter().position(|b| *b == b'=') else {
+                continue;
+            };
+            let (key, sgr) = (&rule[..eq], &rule[eq + 1..]);
+            // ln=target means to color links like the file they point to, which we don't support
+            if sgr == b"target" {
+                continue;
+            }
+            let style = if sgr.iter().all(|b| *b == b'0') {
+                Style::Reset
+            } else {
+                Style::Custom(sgr)
+            };
+            match key.strip_prefix(b"*") {
+                Some(suffix) => match suffix.strip_prefix(b".") {
+                    Some(ext) if !ext.contains(&b'.') => colors.extensions.push((ext, style)),
+                    _ => colors.suffixes.push((suffix, style)),
+                },
+                None => colors.types.push((key, style)),
+            }
+        }
+        colors
+            .extensions
+            .sort_by(|a, b| memcmp_ignore_case(a.0, b.0));
+        colors
+            .extensions
+            .dedup_by(|a, b| memcmp_ignore_case(a.0, b.0).is_eq());
+        colors
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty() && self.extensions.is_empty() && self.suffixes.is_empty()
+    }
+
+    pub fn type_style(&self, key: &[u8]) -> Option<Style> {
+        self.types.iter().find(|t| t.0 == key).map(|t| t.1)
+    }
+
+    /// Whether a key is set to nothing or 00, which turns off coloring that kind of file
+    /// differently, so that it's colored like it would be otherwise
+    pub fn disables(&self, key: &[u8]) -> bool {
+        self.type_style(key) == Some(Style::Reset)
+    }
+
+    pub fn name_style(&self, name: &[u8]) -> Option<Style> {
+        let ends_with = |suffix: &[u8]| {
+            name.len() >= suffix.len()
+                && name[name.len() - suffix.len()..].eq_ignore_ascii_case(suffix)
+        };
+        if let Some(s) = self.suffixes.iter().find(|s| ends_with(s.0)) {
+            return Some(s.1);
+        }
+        let extension = &name[name.iter().rposition(|b| *b == b'.')? + 1..];
+        self.extensions
+            .binary_search_by(|probe| memcmp_ignore_case(probe.0, extension))
+            .ok()
+            .map(|i| self.extensions[i].1)
+    }
+}
@@ -0,0 +1,102 @@
+//! Everything from veneer's syscalls, plus the ones it doesn't wrap
+
+pub use veneer::syscalls::*;
+
+use core::mem;
+use libc::{c_int, c_uint};
+use sc::syscall;
+use veneer::{CStr, Error};
+
+trait SyscallRet {
+    fn usize_result(self) -> Result<usize, Error>;
+}
+
+impl SyscallRet for usize {
+    #[inline]
+    fn usize_result(self) -> Result<usize, Error> {
+        let ret = self as isize;
+        if ret < 0 {
+            Err(Error(-ret as c_int))
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+#[inline]
+pub fn uname() -> Result<libc::utsname, Error> {
+    unsafe {
+        let mut name: libc::utsname = mem::zeroed();
+        syscall!(UNAME, &mut name as *mut libc::utsname).usize_result()?;
+        Ok(name)
+    }
+}
+
+/// Returns the current directory, which is written to the start of buf
+#[inline]
+pub fn getcwd(buf: &mut [u8]) -> Result<&[u8], Error> {
+    let len = unsafe { syscall!(GETCWD, buf.as_mut_ptr(), buf.len()) }.usize_result()?;
+    // The length includes the null terminator
+    Ok(&buf[..len.saturating_sub(1)])
+}
+
+/// Like fstatat, but the kernel only has to fill in the fields in mask, and there's a birth time
+#[inline]
+pub fn statx(dirfd: c_int, name: CStr, flags: c_int, mask: c_uint) -> Result<libc::statx, Error> {
+    unsafe {
+        let mut status: libc::statx = mem::zeroed();
+        syscall!(
+            STATX,
+            dirfd,
+            name.as_ptr(),
+            flags,
+            mask,
+            &mut status as *mut libc::statx
+        )
+        .usize_result()?;
+        Ok(status)
+    }
+}
+
+/// Reads an extended attribute into buf, returning its length. Symlinks are followed.
+#[inline]
+pub fn getxattr(path: CStr, name: CStr, buf: &mut [u8]) -> Result<usize, Error> {
+    unsafe {
+        syscall!(
+            GETXATTR,
+            path.as_ptr(),
+            name.as_ptr(),
+            buf.as_mut_ptr(),
+            buf.len()
+        )
+        .usize_result()
+    }
+}
+
+/// Like getxattr, but reads the attribute of a symlink itself
+#[inline]
+pub fn lgetxattr(path: CStr, name: CStr, buf: &mut [u8]) -> Result<usize, Error> {
+    unsafe {
+        syscall!(
+            LGETXATTR,
+            path.as_ptr(),
+            name.as_ptr(),
+            buf.as_mut_ptr(),
+            buf.len()
+        )
+        .usize_result()
+    }
+}
+
+/// Writes the null-terminated names of a file's extended attributes into buf, returning their
+/// total length. Symlinks are followed.
+#[inline]
+pub fn listxattr(path: CStr, buf: &mut [u8]) -> Result<usize, Error> {
+    unsafe { syscall!(LISTXATTR, path.as_ptr(), buf.as_mut_ptr(), buf.len()).usize_result() }
+}
+
+/// Like listxattr, but lists the attributes of a symlink itself
+#[inline]
+pub fn llistxattr(path: CStr, buf: &mut [u8]) -> Result<usize, Error> {
+    unsafe { syscall!(LLISTXATTR, path.as_ptr(), buf.as_mut_ptr(), buf.len()).usize_result() }
+}
@@ -1,3 +1,8 @@
+use crate::{
+    output::OutputBuffer,
+    utils::{atoi, fs_read, Buffer, SliceExt},
+    CStr,
+};
 use alloc::vec::Vec;
 use core::convert::TryInto;
 
@@ -9,83 +14,84 @@ const DAYS_PER_4Y: i64 = 365 * 4 + 1;
 
 const DAYS_IN_MONTH: [u8; 12] = [31, 30, 31, 30, 31, 31, 30, 31, 30, 31, 31, 29];
 
-trait SliceExt: Sized {
-    fn read_u32_be(&mut self) -> Option<u32>;
-    fn read(&mut self, n: usize) -> Option<Self>;
-}
+const HEADER_LEN: usize = 0x2C;
 
-impl SliceExt for &[u8] {
-    fn read(&mut self, n: usize) -> Option<Self> {
-        if self.len() < n {
-            None
-        } else {
-            let (head, tail) = self.split_at(n);
-            *self = tail;
-            Some(head)
-        }
-    }
+/// The counts from a TZif header, which determine the layout of the data block after it
+struct Header {
+    version: u8,
+    ttisutcnt: usize,
+    ttisstdcnt: usize,
+    leapcnt: usize,
+    timecnt: usize,
+    typecnt: usize,
+    charcnt: usize,
+}
 
-    fn read_u32_be(&mut self) -> Option<u32> {
-        if self.len() < 4 {
-            return None;
-        }
-        let (head, tail) = self.split_at(4);
-        *self = tail;
-        Some(u32::from_be_bytes(head.try_into().unwrap()))
+impl Header {
+    /// The length of the data block, where times are either 4 (version 1) or 8 bytes long
+    fn data_len(&self, time_len: usize) -> usize {
+        self.timecnt * time_len
+            + self.timecnt
+            + self.typecnt * 6
+            + self.charcnt
+            + self.leapcnt * (time_len + 4)
+            + self.ttisstdcnt
+            + self.ttisutcnt
     }
 }
 
-fn parse_header(buffer: &[u8]) -> Option<Header> {
-    // We only support version 2, so validate the magic bytes and version all at once.
-    if buffer.get(..5)? != b"TZif2" {
+fn parse_header(buffer: &mut &[u8]) -> Option<Header> {
+    let header = buffer.read(HEADER_LEN)?;
+    if header[..4] != *b"TZif" {
         return None;
     }
-    let mut header = buffer.get(0x14..=0x2b)?;
-    let tzh_ttisgmtcnt = header.read_u32_be()?;
-    let tzh_ttisstdcnt = header.read_u32_be()?;
-    let tzh_leapcnt = header.read_u32_be()?;
-    let tzh_timecnt = header.read_u32_be()?;
-    let tzh_typecnt = header.read_u32_be()?;
-    let tzh_charcnt = header.read_u32_be()?;
-    // V2 format data start
-    let s = (tzh_timecnt * 5
-        + tzh_typecnt * 6
-        + tzh_leapcnt * 8
-        + tzh_charcnt
-        + tzh_ttisstdcnt
-        + tzh_ttisgmtcnt
-        + 44) as usize;
-    let mut header = buffer.get(s + 0x14..=s + 0x2b)?;
-    let _ignored_fields = header.read(12)?;
+    let mut counts = &header[0x14..];
     Some(Header {
-        tzh_timecnt: header.read_u32_be()?,
-        tzh_typecnt: header.read_u32_be()?,
-        v2_header_start: s,
+        version: header[4],
+        ttisutcnt: counts.read_u32_be()? as usize,
+        ttisstdcnt: counts.read_u32_be()? as usize,
+        leapcnt: counts.read_u32_be()? as usize,
+        timecnt: counts.read_u32_be()? as usize,
+        typecnt: counts.read_u32_be()? as usize,
+        charcnt: counts.read_u32_be()? as usize,
     })
 }
 
-fn parse_data(buffer: &[u8], header: Header) -> Option<Tzinfo> {
-    let mut buffer = buffer.get(HEADER_LEN + header.v2_header_start..)?;
-
-    // Extracting data fields
-    let tzh_timecnt_data: Vec<i64> = buffer
-        .read(header.tzh_timecnt as usize * 8)?
-        .chunks_exact(8)
-        .map(read_i64)
+fn parse_data(buffer: &mut &[u8], header: &Header, time_len: usize) -> Option<Tzinfo> {
+    let tzh_timecnt_data = buffer
+        .read(header.timecnt * time_len)?
+        .chunks_exact(time_len)
+        .map(|t| {
+            if time_len == 8 {
+                read_i64(t)
+            } else {
+                read_i32(t) as i64
+            }
+        })
         .collect();
 
-    let tzh_timecnt_indices = buffer.read(header.tzh_timecnt as usize)?.to_vec();
+    let tzh_timecnt_indices = buffer.read(header.timecnt)?.to_vec();
 
-    let gmt_offsets: Vec<_> = buffer
-        .read(header.tzh_typecnt as usize * 6)?
+    let types = buffer.read(header.typecnt * 6)?;
+    let gmt_offsets = types
         .chunks_exact(6)
         .map(|tti| read_i32(&tti[..4]))
         .collect();
+    let abbreviation_indices = types.chunks_exact(6).map(|tti| tti[5]).collect();
+
+    let abbreviations = buffer.read(header.charcnt)?.to_vec();
+
+    // Leap seconds, and the standard/wall and UT/local indicators
+    let _ignored_fields =
+        buffer.read(header.leapcnt * (time_len + 4) + header.ttisstdcnt + header.ttisutcnt)?;
 
     Some(Tzinfo {
         tzh_timecnt_data,
         tzh_timecnt_indices,
         gmt_offsets,
+        abbreviation_indices,
+        abbreviations,
+        rule: None,
     })
 }
 
@@ -97,58 +103,137 @@ fn read_i64(bytes: &[u8]) -> i64 {
     i64::from_be_bytes(bytes[..8].try_into().unwrap())
 }
 
-const HEADER_LEN: usize = 0x2C;
-
-struct Header {
-    //tzh_ttisgmtcnt: u32,
-    //tzh_ttisstdcnt: u32,
-    //tzh_leapcnt: u32,
-    tzh_timecnt: u32,
-    tzh_typecnt: u32,
-    //tzh_charcnt: u32,
-    v2_header_start: usize,
-}
-
 pub struct Tzinfo {
     /// transition times timestamps table
     tzh_timecnt_data: Vec<i64>,
     /// indices for the next field
     tzh_timecnt_indices: Vec<u8>,
     gmt_offsets: Vec<i32>,
+    /// Where each type's abbreviation for %Z starts in abbreviations
+    abbreviation_indices: Vec<u8>,
+    /// Null-terminated abbreviations, like CET and CEST
+    abbreviations: Vec<u8>,
+    /// Applies to all times after the last transition
+    rule: Option<PosixTz>,
 }
 
-pub struct LocalTime {
+pub struct LocalTime<'a> {
     pub year: i32,
     pub month: i32,
     pub day_of_month: i32,
     pub hour: i32,
     pub minute: i32,
+    pub second: i32,
+    pub nanosecond: i32,
+    pub day_of_week: i32,
+    pub day_of_year: i32,
+    /// Seconds east of UTC
+    pub gmt_offset: i32,
+    /// The abbreviation for the time zone, like CEST
+    pub zone: &'a [u8],
+    /// Seconds since the epoch, for %s
+    pub timestamp: i64,
 }
 
 impl Tzinfo {
+    /// Parses a TZif file of any version. Version 2 and later repeat all the data with 64-bit
+    /// times, then end with a POSIX TZ rule for times past the last transition.
     #[inline(never)]
-    pub fn new(zi: &[u8]) -> Self {
-        let header = parse_header(zi).unwrap();
-        parse_data(zi, header).unwrap()
+    pub fn new(zi: &[u8]) -> Option<Self> {
+        let mut buffer = zi;
+        let header = parse_header(&mut buffer)?;
+        if header.version == 0 {
+            return parse_data(&mut buffer, &header, 4);
+        }
+        buffer.read(header.data_len(4))?;
+        let header = parse_header(&mut buffer)?;
+        let mut tzinfo = parse_data(&mut buffer, &header, 8)?;
+        tzinfo.rule = buffer
+            .strip_prefix(b"\n")
+            .and_then(|footer| footer.split(|b| *b == b'\n').next())
+            .and_then(PosixTz::parse);
+        Some(tzinfo)
+    }
+
+    pub fn utc() -> Self {
+        Self {
+            tzh_timecnt_data: Vec::new(),
+            tzh_timecnt_indices: Vec::new(),
+            gmt_offsets: Vec::new(),
+            abbreviation_indices: Vec::new(),
+            abbreviations: Vec::new(),
+            rule: None,
+        }
+    }
+
+    /// Finds the time zone the way glibc interprets TZ: unset means /etc/localtime, otherwise it
+    /// names a file (optionally after a :) either absolute or under /usr/share/zoneinfo, or is a
+    /// POSIX rule. Anything we can't make sense of is UTC, without complaint.
+    pub fn load(tz: Option<&[u8]>) -> Self {
+        let tz = match tz {
+            None => return Self::read(b"/etc/localtime".to_vec()).unwrap_or_else(Self::utc),
+            Some(tz) => tz.strip_prefix(b":").unwrap_or(tz),
+        };
+        if tz.is_empty() {
+            return Self::utc();
+        }
+        let path = if tz.starts_with(b"/") {
+            tz.to_vec()
+        } else {
+            [&b"/usr/share/zoneinfo/"[..], tz].concat()
+        };
+        Self::read(path)
+            .or_else(|| {
+                let rule = PosixTz::parse(tz)?;
+                Some(Self {
+                    rule: Some(rule),
+                    ..Self::utc()
+                })
+            })
+            .unwrap_or_else(Self::utc)
+    }
+
+    fn read(mut path: Vec<u8>) -> Option<Self> {
+        path.push(0);
+        let zi = fs_read(CStr::from_bytes(&path)).ok()?;
+        Self::new(&zi)
     }
 
-    fn gmt_offset(&self, time: i64) -> i64 {
-        let best_idx = match self.tzh_timecnt_data.binary_search(&time) {
-            Ok(i) => i,
-            Err(i) => i + 1,
+    /// The offset from UTC at a time, and the abbreviation for it
+    fn local_type(&self, time: i64) -> (i64, &[u8]) {
+        // The number of transitions at or before this time
+        let passed = match self.tzh_timecnt_data.binary_search(&time) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+        if passed == self.tzh_timecnt_data.len() {
+            if let Some(rule) = &self.rule {
+                return rule.local_type(time);
+            }
+        }
+        // Times before the first transition use the first type
+        let idx = match passed.checked_sub(1) {
+            Some(i) => self.tzh_timecnt_indices[i] as usize,
+            None => 0,
+        };
+        let Some(gmt_offset) = self.gmt_offsets.get(idx) else {
+            return (0, b"UTC");
         };
-        let idx = *self
-            .tzh_timecnt_indices
-            .get(best_idx)
-            .or_else(|| self.tzh_timecnt_indices.last())
-            .unwrap_or(&0) as usize;
-        *self.gmt_offsets.get(idx).unwrap_or(&0) as i64
+        let start = self.abbreviation_indices[idx] as usize;
+        let abbreviation = self.abbreviations.get(start..).unwrap_or_default();
+        let len = abbreviation.iter().position(|b| *b == 0);
+        (
+            *gmt_offset as i64,
+            &abbreviation[..len.unwrap_or(abbreviation.len())],
+        )
     }
 
     // Ported from musl's localtime_r impl, src/time/__secs_to_tm.c
     #[inline(never)]
-    pub fn convert_to_localtime(&self, t: i64) -> LocalTime {
-        let t = t + self.gmt_offset(t);
+    pub fn convert_to_localtime(&self, t: i64, nanoseconds: i64) -> LocalTime<'_> {
+        let timestamp = t;
+        let (gmt_offset, zone) = self.local_type(t);
+        let t = t + gmt_offset;
 
         let secs = t - LEAPOCH;
         let mut days = secs / 86400;
@@ -158,6 +243,11 @@ impl Tzinfo {
             days -= 1;
         }
 
+        let mut day_of_week = (3 + days) % 7;
+        if day_of_week < 0 {
+            day_of_week += 7;
+        }
+
         let mut qc_cycles = days / DAYS_PER_400Y;
         let mut remdays = days % DAYS_PER_400Y;
         if remdays < 0 {
@@ -183,6 +273,12 @@ impl Tzinfo {
         }
         remdays -= remyears * 365;
 
+        let leap = (remyears == 0 && (q_cycles != 0 || c_cycles == 0)) as i64;
+        let mut day_of_year = remdays + 31 + 28 + leap;
+        if day_of_year >= 365 + leap {
+            day_of_year -= 365 + leap;
+        }
+
         let mut years = remyears + 4 * q_cycles + 100 * c_cycles + 400 * qc_cycles;
 
         let mut months: i64 = 0;
@@ -202,6 +298,481 @@ impl Tzinfo {
             day_of_month: (remdays + 1).try_into().unwrap(),
             hour: (remsecs / 3600).try_into().unwrap(),
             minute: (remsecs / 60 % 60).try_into().unwrap(),
+            second: (remsecs % 60).try_into().unwrap(),
+            nanosecond: nanoseconds.try_into().unwrap_or(0),
+            day_of_week: day_of_week.try_into().unwrap(),
+            day_of_year: day_of_year.try_into().unwrap(),
+            gmt_offset: gmt_offset.try_into().unwrap_or(0),
+            zone,
+            timestamp,
+        }
+    }
+}
+
+/// A POSIX TZ rule such as CET-1CEST,M3.5.0,M10.5.0/3, from the TZ variable or a TZif footer
+struct PosixTz {
+    std_name: Vec<u8>,
+    /// Seconds east of UTC, which is the opposite sign from how it is written
+    std_offset: i64,
+    dst: Option<DstRule>,
+}
+
+struct DstRule {
+    name: Vec<u8>,
+    offset: i64,
+    /// In local standard time
+    start: Transition,
+    /// In local daylight saving time
+    end: Transition,
+}
+
+struct Transition {
+    day: RuleDay,
+    /// Seconds after midnight, which may be negative or past the end of the day
+    time: i64,
+}
+
+enum RuleDay {
+    /// Jn: 1 to 365, where February 29th is never counted
+    Julian(i64),
+    /// n: 0 to 365, where February 29th is counted in leap years
+    ZeroBased(i64),
+    /// Mm.w.d: weekday d (0 is Sunday) of week w (5 is the last) of month m
+    MonthWeekDay {
+        month: usize,
+        week: i64,
+        weekday: i64,
+    },
+}
+
+const DAYS_BEFORE_MONTH: [i64; 13] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334, 365];
+
+impl PosixTz {
+    fn parse(mut spec: &[u8]) -> Option<Self> {
+        let s = &mut spec;
+        let std_name = parse_zone_name(s)?.to_vec();
+        let std_offset = -parse_time(s)?;
+        if s.is_empty() {
+            return Some(Self {
+                std_name,
+                std_offset,
+                dst: None,
+            });
+        }
+
+        let name = parse_zone_name(s)?.to_vec();
+        let offset = match s.first() {
+            Some(b',') | None => std_offset + 3600,
+            Some(_) => -parse_time(s)?,
+        };
+        let (start, end) = if s.is_empty() {
+            // Like glibc, use the US rules when none are given
+            let month_week_day = |month, week| Transition {
+                day: RuleDay::MonthWeekDay {
+                    month,
+                    week,
+                    weekday: 0,
+                },
+                time: 2 * 3600,
+            };
+            (month_week_day(3, 2), month_week_day(11, 1))
+        } else {
+            *s = s.strip_prefix(b",")?;
+            let start = parse_transition(s)?;
+            *s = s.strip_prefix(b",")?;
+            let end = parse_transition(s)?;
+            (start, end)
+        };
+        if !s.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            std_name,
+            std_offset,
+            dst: Some(DstRule {
+                name,
+                offset,
+                start,
+                end,
+            }),
+        })
+    }
+
+    fn local_type(&self, time: i64) -> (i64, &[u8]) {
+        let Some(dst) = &self.dst else {
+            return (self.std_offset, &self.std_name);
+        };
+        let year = year_containing(time + self.std_offset);
+        let start = dst.start.local_time(year) - self.std_offset;
+        let end = dst.end.local_time(year) - dst.offset;
+        // In the southern hemisphere, daylight saving time spans the new year
+        let in_dst = if start < end {
+            start <= time && time < end
+        } else {
+            !(end <= time && time < start)
+        };
+        if in_dst {
+            (dst.offset, &dst.name)
+        } else {
+            (self.std_offset, &self.std_name)
         }
     }
 }
+
+impl Transition {
+    /// Seconds since the epoch until this transition, as if the local time were UTC
+    fn local_time(&self, year: i64) -> i64 {
+        let leap = is_leap_year(year) as i64;
+        let day_of_year = match self.day {
+            RuleDay::Julian(n) => n - 1 + (n >= 60) as i64 * leap,
+            RuleDay::ZeroBased(n) => n,
+            RuleDay::MonthWeekDay {
+                month,
+                week,
+                weekday,
+            } => {
+                let leap_day = |m| (m > 2) as i64 * leap;
+                let first = DAYS_BEFORE_MONTH[month - 1] + leap_day(month);
+                let month_len = DAYS_BEFORE_MONTH[month] + leap_day(month + 1) - first;
+                let first_weekday = (days_before_year(year) + first + 4).rem_euclid(7);
+                let mut day = (weekday - first_weekday).rem_euclid(7) + (week - 1) * 7;
+                if day >= month_len {
+                    day -= 7;
+                }
+                first + day
+            }
+        };
+        (days_before_year(year) + day_of_year) * 86400 + self.time
+    }
+}
+
+/// A zone name is letters, or anything between < and >, like <+03>. The name is what %Z prints.
+fn parse_zone_name<'a>(s: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let name = if let Some(quoted) = s.strip_prefix(b"<") {
+        let len = quoted.iter().position(|b| *b == b'>')?;
+        *s = &quoted[len + 1..];
+        &quoted[..len]
+    } else {
+        let len = s.iter().take_while(|b| b.is_ascii_alphabetic()).count();
+        let name = &s[..len];
+        *s = &s[len..];
+        name
+    };
+    (name.len() >= 3).then_some(name)
+}
+
+fn parse_number(s: &mut &[u8]) -> Option<i64> {
+    let len = s.iter().take_while(|b| b.is_ascii_digit()).count();
+    if len == 0 || len > 3 {
+        return None;
+    }
+    let number = atoi(&s[..len]) as i64;
+    *s = &s[len..];
+    Some(number)
+}
+
+/// Parses [+|-]hh[:mm[:ss]] into seconds
+fn parse_time(s: &mut &[u8]) -> Option<i64> {
+    let sign = match s.first() {
+        Some(b'-') => -1,
+        _ => 1,
+    };
+    if let Some(b'+' | b'-') = s.first() {
+        *s = &s[1..];
+    }
+    let mut seconds = parse_number(s)? * 3600;
+    for unit in [60, 1] {
+        match s.strip_prefix(b":") {
+            Some(rest) => *s = rest,
+            None => break,
+        }
+        seconds += parse_number(s)? * unit;
+    }
+    Some(sign * seconds)
+}
+
+fn parse_transition(s: &mut &[u8]) -> Option<Transition> {
+    let day = if let Some(rest) = s.strip_prefix(b"J") {
+        *s = rest;
+        RuleDay::Julian(parse_number(s).filter(|n| (1..=365).contains(n))?)
+    } else if let Some(rest) = s.strip_prefix(b"M") {
+        *s = rest;
+        let month = parse_number(s).filter(|m| (1..=12).contains(m))?;
+        *s = s.strip_prefix(b".")?;
+        let week = parse_number(s).filter(|w| (1..=5).contains(w))?;
+        *s = s.strip_prefix(b".")?;
+        let weekday = parse_number(s).filter(|d| (0..=6).contains(d))?;
+        RuleDay::MonthWeekDay {
+            month: month as usize,
+            week,
+            weekday,
+        }
+    } else {
+        RuleDay::ZeroBased(parse_number(s).filter(|n| (0..=365).contains(n))?)
+    };
+    let time = match s.strip_prefix(b"/") {
+        Some(rest) => {
+            *s = rest;
+            parse_time(s)?
+        }
+        None => 2 * 3600,
+    };
+    Some(Transition { day, time })
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// Days from the epoch to January 1st of the year
+fn days_before_year(year: i64) -> i64 {
+    let leap_days = |y: i64| y.div_euclid(4) - y.div_euclid(100) + y.div_euclid(400);
+    365 * (year - 1970) + leap_days(year - 1) - leap_days(1969)
+}
+
+fn year_containing(time: i64) -> i64 {
+    let days = time.div_euclid(86400);
+    let mut year = 1970 + (days * 400).div_euclid(DAYS_PER_400Y);
+    while days_before_year(year + 1) <= days {
+        year += 1;
+    }
+    while days_before_year(year) > days {
+        year -= 1;
+    }
+    year
+}
+
+static MONTH_NAMES: &[&[u8]] = &[
+    b"January",
+    b"February",
+    b"March",
+    b"April",
+    b"May",
+    b"June",
+    b"July",
+    b"August",
+    b"September",
+    b"October",
+    b"November",
+    b"December",
+];
+
+static DAY_NAMES: &[&[u8]] = &[
+    b"Sunday",
+    b"Monday",
+    b"Tuesday",
+    b"Wednesday",
+    b"Thursday",
+    b"Friday",
+    b"Saturday",
+];
+
+/// The strftime formats for files modified in the last six months, and for all others
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct TimeStyle {
+    pub recent: &'static [u8],
+    pub old: &'static [u8],
+}
+
+impl TimeStyle {
+    pub const LOCALE: Self = Self {
+        recent: b"%b %e %H:%M",
+        old: b"%b %e  %Y",
+    };
+    pub const ISO: Self = Self {
+        recent: b"%m-%d %H:%M",
+        old: b"%Y-%m-%d ",
+    };
+    pub const LONG_ISO: Self = Self::both(b"%Y-%m-%d %H:%M");
+    pub const FULL_ISO: Self = Self::both(b"%Y-%m-%d %H:%M:%S.%N %z");
+
+    const fn both(format: &'static [u8]) -> Self {
+        Self {
+            recent: format,
+            old: format,
+        }
+    }
+
+    /// Parses +FORMAT, or +OLD_FORMAT<newline>RECENT_FORMAT like GNU ls
+    pub fn custom(format: &'static [u8]) -> Self {
+        match format.iter().position(|b| *b == b'\n') {
+            Some(i) => Self {
+                recent: &format[i + 1..],
+                old: &format[..i],
+            },
+            None => Self::both(format),
+        }
+    }
+}
+
+/// Where a formatted time goes, so that it can be measured without being printed
+pub trait TimeSink {
+    fn push(&mut self, b: u8);
+    fn write(&mut self, bytes: &[u8]);
+}
+
+impl TimeSink for OutputBuffer {
+    fn push(&mut self, b: u8) {
+        OutputBuffer::push(self, b);
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        OutputBuffer::write(self, bytes);
+    }
+}
+
+impl TimeSink for Vec<u8> {
+    fn push(&mut self, b: u8) {
+        Vec::push(self, b);
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+}
+
+impl LocalTime<'_> {
+    /// Writes this time according to a strftime format, in the C locale. The -, _, and 0 flags
+    /// are supported, and unknown conversions are printed as-is.
+    #[inline(never)]
+    pub fn format(&self, format: &[u8], out: &mut impl TimeSink) {
+        let mut bytes = format.iter().copied();
+        while let Some(b) = bytes.next() {
+            if b != b'%' {
+                out.push(b);
+                continue;
+            }
+            let mut pad = None;
+            let mut conversion = bytes.next();
+            if let Some(flag @ (b'-' | b'_' | b'0')) = conversion {
+                pad = Some(flag);
+                conversion = bytes.next();
+            }
+            let (value, width, default_pad) = match conversion {
+                Some(b'Y') => (self.year as i64 + 1900, 4, b'0'),
+                Some(b'y') => ((self.year as i64 + 1900) % 100, 2, b'0'),
+                Some(b'C') => ((self.year as i64 + 1900) / 100, 2, b'0'),
+                Some(b'm') => (self.month as i64 + 1, 2, b'0'),
+                Some(b'd') => (self.day_of_month as i64, 2, b'0'),
+                Some(b'e') => (self.day_of_month as i64, 2, b' '),
+                Some(b'j') => (self.day_of_year as i64 + 1, 3, b'0'),
+                Some(b'H') => (self.hour as i64, 2, b'0'),
+                Some(b'k') => (self.hour as i64, 2, b' '),
+                Some(b'I') => ((self.hour as i64 + 11) % 12 + 1, 2, b'0'),
+                Some(b'l') => ((self.hour as i64 + 11) % 12 + 1, 2, b' '),
+                Some(b'M') => (self.minute as i64, 2, b'0'),
+                Some(b'S') => (self.second as i64, 2, b'0'),
+                Some(b'N') => (self.nanosecond as i64, 9, b'0'),
+                Some(b'u') => ((self.day_of_week as i64 + 6) % 7 + 1, 1, b'0'),
+                Some(b'w') => (self.day_of_week as i64, 1, b'0'),
+                Some(b'b' | b'h') => {
+                    out.write(&self.month_name()[..3]);
+                    continue;
+                }
+                Some(b'B') => {
+                    out.write(self.month_name());
+                    continue;
+                }
+                Some(b'a') => {
+                    out.write(&self.day_name()[..3]);
+                    continue;
+                }
+                Some(b'A') => {
+                    out.write(self.day_name());
+                    continue;
+                }
+                Some(b'p') => {
+                    out.write(if self.hour < 12 { b"AM" } else { b"PM" });
+                    continue;
+                }
+                Some(b'Z') => {
+                    out.write(self.zone);
+                    continue;
+                }
+                Some(b's') => {
+                    if self.timestamp < 0 {
+                        out.push(b'-');
+                    }
+                    write_padded(out, self.timestamp.unsigned_abs(), 0, b'0');
+                    continue;
+                }
+                Some(b'z') => {
+                    let offset = self.gmt_offset.unsigned_abs() / 60;
+                    out.push(if self.gmt_offset < 0 { b'-' } else { b'+' });
+                    write_padded(out, (offset / 60 * 100 + offset % 60) as u64, 4, b'0');
+                    continue;
+                }
+                Some(b'F') => {
+                    self.format(b"%Y-%m-%d", out);
+                    continue;
+                }
+                Some(b'T') => {
+                    self.format(b"%H:%M:%S", out);
+                    continue;
+                }
+                Some(b'R') => {
+                    self.format(b"%H:%M", out);
+                    continue;
+                }
+                Some(b'D') => {
+                    self.format(b"%m/%d/%y", out);
+                    continue;
+                }
+                Some(b'c') => {
+                    self.format(b"%a %b %e %H:%M:%S %Y", out);
+                    continue;
+                }
+                Some(b'n') => {
+                    out.push(b'\n');
+                    continue;
+                }
+                Some(b't') => {
+                    out.push(b'\t');
+                    continue;
+                }
+                Some(b'%') => {
+                    out.push(b'%');
+                    continue;
+                }
+                Some(other) => {
+                    out.write(&[b'%', other]);
+                    continue;
+                }
+                None => {
+                    out.push(b'%');
+                    break;
+                }
+            };
+            let value = value.max(0) as u64;
+            match pad.unwrap_or(default_pad) {
+                b'-' => write_padded(out, value, 0, b'0'),
+                b'_' => write_padded(out, value, width, b' '),
+                pad => write_padded(out, value, width, pad),
+            }
+        }
+    }
+
+    fn month_name(&self) -> &'static [u8] {
+        MONTH_NAMES
+            .get(self.month as usize)
+            .copied()
+            .unwrap_or(b"???")
+    }
+
+    fn day_name(&self) -> &'static [u8] {
+        DAY_NAMES
+            .get(self.day_of_week as usize)
+            .copied()
+            .unwrap_or(b"???")
+    }
+}
+
+fn write_padded(out: &mut impl TimeSink, value: u64, width: usize, pad: u8) {
+    let mut buf = Buffer::new();
+    let formatted = buf.format(value);
+    for _ in formatted.len()..width {
+        out.push(pad);
+    }
+    out.write(formatted);
+}
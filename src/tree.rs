@@ -0,0 +1,325 @@
+use crate::{
+    access_error,
+    cli::{App, DisplayMode},
+    directory::{DirEntry, DirEntryExt},
+    enter_dir, leave_dir,
+    output::DetailsLayout,
+    print, read_entries, syscalls, Status, Style,
+};
+use alloc::vec::Vec;
+use veneer::{
+    fs::{DType, Directory},
+    CStr, Error,
+};
+
+/// The pieces that the guides to the left of each name are made of
+struct Guides {
+    branch: &'static [u8],
+    last_branch: &'static [u8],
+    /// Continues the line down past a directory's entries when there are more after it
+    line: &'static [u8],
+    blank: &'static [u8],
+}
+
+static BOX_DRAWING: Guides = Guides {
+    branch: "├── ".as_bytes(),
+    last_branch: "└── ".as_bytes(),
+    line: "│   ".as_bytes(),
+    blank: b"    ",
+};
+
+static ASCII: Guides = Guides {
+    branch: b"|-- ",
+    last_branch: b"`-- ",
+    line: b"|   ",
+    blank: b"    ",
+};
+
+struct Tree {
+    /// The device and inode of each directory we're inside of, so that we don't go around in
+    /// circles when following symlinks
+    stack: Vec<(libc::dev_t, libc::ino_t)>,
+    /// The guides for the directories we're inside of, then for the entry being printed
+    prefix: Vec<u8>,
+    guides: &'static Guides,
+    /// With -l, the columns are lined up across the whole tree, so the walk keeps what it finds
+    /// and measures it, and the tree is printed once it's done
+    collecting: bool,
+    layout: Option<DetailsLayout>,
+}
+
+/// What the walk found in a directory, when it's printed afterwards
+#[derive(Default)]
+struct Listing {
+    /// The names of the entries, each null-terminated
+    names: Vec<u8>,
+    /// Where each name is in names, and the rest of its DirEntry
+    entries: Vec<((usize, usize), libc::c_ulong, DType)>,
+    statuses: Vec<Option<Status>>,
+    /// The listing of each entry that is a directory we went into
+    children: Vec<Option<Listing>>,
+}
+
+impl Listing {
+    fn push(&mut self, entry: (DirEntry, Option<Status>), children: Option<Listing>) {
+        let start = self.names.len();
+        self.names.extend_from_slice(entry.0.name.as_bytes());
+        self.names.push(0);
+        self.entries
+            .push(((start, self.names.len()), entry.0.inode, entry.0.d_type));
+        self.statuses.push(entry.1);
+        self.children.push(children);
+    }
+}
+
+/// Lists a directory and everything under it as one tree
+pub fn write_tree(path: &[u8], app: &mut App) {
+    let mut path = path.to_vec();
+    path.push(0);
+    let mut tree = Tree {
+        stack: Vec::new(),
+        prefix: Vec::new(),
+        guides: if app.ascii_guides {
+            &ASCII
+        } else {
+            &BOX_DRAWING
+        },
+        collecting: app.display_mode == (DisplayMode::Tree { long: true }),
+        layout: None,
+    };
+
+    let listing = if tree.collecting {
+        tree.walk_from(&mut path, app)
+    } else {
+        None
+    };
+
+    let root = &path[..path.len() - 1];
+    let style = app.ls_colors.type_style(b"di").unwrap_or(Style::BlueBold);
+    app.out.style(style).write_name(root);
+    app.out.style(Style::Reset).push(b'\n');
+    if let Some(listing) = listing {
+        tree.write_listing(&mut path, listing, app);
+    } else if !tree.collecting {
+        tree.walk_from(&mut path, app);
+    }
+    app.out.flush();
+}
+
+impl Tree {
+    /// Walks the tree under a null-terminated path. Unless we're collecting, entries are printed
+    /// as they're found and the Listing that comes back is empty.
+    fn walk_from(&mut self, path: &mut Vec<u8>, app: &mut App) -> Option<Listing> {
+        let dir = match Directory::open(CStr::from_bytes(path)) {
+            Ok(dir) => dir,
+            Err(err) => {
+                access_error(app, &path[..path.len() - 1], err);
+                return None;
+            }
+        };
+        path.pop();
+        let status = syscalls::fstat(dir.raw_fd()).unwrap();
+        self.stack.push((status.st_dev, status.st_ino));
+        let listing = self.walk(path, &dir, 1, app);
+        self.stack.pop();
+        path.push(0);
+        Some(listing)
+    }
+
+    fn walk(
+        &mut self,
+        path: &mut Vec<u8>,
+        dir: &Directory,
+        depth: usize,
+        app: &mut App,
+    ) -> Listing {
+        let mut listing = Listing::default();
+        let contents = match dir.read() {
+            Ok(c) => c,
+            Err(err) => {
+                access_error(app, path, err);
+                return listing;
+            }
+        };
+        enter_dir(dir, path, app);
+        let entries = read_entries(&contents, dir, app, true);
+
+        if self.collecting {
+            let layout = DetailsLayout::measure(&entries, app);
+            self.layout = Some(match self.layout {
+                Some(previous) => previous.merge(layout),
+                None => layout,
+            });
+        }
+
+        let count = entries.len();
+        for (i, entry) in entries.into_iter().enumerate() {
+            let last = i + 1 == count;
+            if !self.collecting {
+                self.write_entry(&entry, dir, last, app);
+            }
+            let name = entry.name().as_bytes();
+            let children = if app.tree_level.is_some_and(|level| depth >= level)
+                || name == b"."
+                || name == b".."
+                || !might_be_directory(&entry)
+            {
+                None
+            } else {
+                self.walk_into(path, name, last, depth, app)
+            };
+            if self.collecting {
+                listing.push(entry, children);
+            }
+        }
+
+        leave_dir(app);
+        listing
+    }
+
+    /// Walks into an entry of the directory at path, if it's a directory we haven't come through
+    fn walk_into(
+        &mut self,
+        path: &mut Vec<u8>,
+        name: &[u8],
+        last: bool,
+        depth: usize,
+        app: &mut App,
+    ) -> Option<Listing> {
+        let path_len = path.len();
+        if path.last() != Some(&b'/') {
+            path.push(b'/');
+        }
+        path.extend(name);
+        path.push(0);
+        let mut listing = None;
+        match Directory::open(CStr::from_bytes(path)) {
+            Ok(child) => {
+                path.pop();
+                let status = syscalls::fstat(child.raw_fd()).unwrap();
+                if !self.stack.contains(&(status.st_dev, status.st_ino)) {
+                    let prefix_len = self.prefix.len();
+                    self.push_guide(last);
+                    self.stack.push((status.st_dev, status.st_ino));
+                    listing = Some(self.walk(path, &child, depth + 1, app));
+                    self.stack.pop();
+                    self.prefix.truncate(prefix_len);
+                }
+            }
+            // Without a d_type or a Status we had to try, and it wasn't a directory
+            Err(Error(libc::ENOTDIR)) => {}
+            Err(err) => access_error(app, &path[..path.len() - 1], err),
+        }
+        path.truncate(path_len);
+        listing
+    }
+
+    /// Prints what the walk collected from the directory at the null-terminated path
+    fn write_listing(&mut self, path: &mut Vec<u8>, listing: Listing, app: &mut App) {
+        // Everything was read by the walk, but printing looks up link targets and such relative
+        // to the directory
+        let dir = match Directory::open(CStr::from_bytes(path)) {
+            Ok(dir) => dir,
+            Err(err) => {
+                access_error(app, &path[..path.len() - 1], err);
+                return;
+            }
+        };
+        path.pop();
+        enter_dir(&dir, path, app);
+
+        let Listing {
+            names,
+            entries,
+            statuses,
+            children,
+        } = listing;
+        let entries: Vec<(DirEntry, Option<Status>)> = entries
+            .iter()
+            .zip(statuses)
+            .map(|(((start, end), inode, d_type), status)| {
+                let entry = DirEntry {
+                    name: CStr::from_bytes(&names[*start..*end]),
+                    inode: *inode,
+                    d_type: *d_type,
+                };
+                (entry, status)
+            })
+            .collect();
+
+        for (i, (entry, children)) in entries.iter().zip(children).enumerate() {
+            let last = i + 1 == entries.len();
+            self.write_entry(entry, &dir, last, app);
+            if let Some(children) = children {
+                let path_len = path.len();
+                if path.last() != Some(&b'/') {
+                    path.push(b'/');
+                }
+                path.extend(entry.name().as_bytes());
+                path.push(0);
+                let prefix_len = self.prefix.len();
+                self.push_guide(last);
+                self.write_listing(path, children, app);
+                self.prefix.truncate(prefix_len);
+                path.truncate(path_len);
+            }
+        }
+
+        leave_dir(app);
+        path.push(0);
+    }
+
+    /// Continues the guides down past a directory's entries, if more come after it
+    fn push_guide(&mut self, last: bool) {
+        self.prefix.extend(if last {
+            self.guides.blank
+        } else {
+            self.guides.line
+        });
+    }
+
+    fn write_entry(
+        &mut self,
+        entry: &(DirEntry, Option<Status>),
+        dir: &Directory,
+        last: bool,
+        app: &mut App,
+    ) {
+        let prefix_len = self.prefix.len();
+        self.prefix.extend(if last {
+            self.guides.last_branch
+        } else {
+            self.guides.branch
+        });
+
+        if let Some(layout) = &self.layout {
+            // Lines under the entry, like its xattrs, continue the guides down
+            let mut below = self.prefix[..prefix_len].to_vec();
+            below.extend(if last {
+                self.guides.blank
+            } else {
+                self.guides.line
+            });
+            layout.write_row(entry, dir, app, &self.prefix, &below);
+        } else {
+            if let Some(status) = crate::git::status(app, entry) {
+                print!(app, status, " ");
+            }
+            app.out.style(Style::Gray).write(&self.prefix);
+            let (style, suffix) = entry.style(dir, app);
+            app.out
+                .style(style)
+                .write_entry_name(entry.name().as_bytes());
+            print!(app, suffix.map(|s| (Style::White, s)), Style::Reset, "\n");
+        }
+
+        self.prefix.truncate(prefix_len);
+    }
+}
+
+fn might_be_directory(entry: &(DirEntry, Option<Status>)) -> bool {
+    match &entry.1 {
+        Some(status) => status.mode & libc::S_IFMT == libc::S_IFDIR,
+        None => entry.0.d_type == DType::DIR || entry.0.d_type == DType::UNKNOWN,
+    }
+}
@@ -3,6 +3,7 @@ use crate::{
     CStr,
 };
 use alloc::vec::Vec;
+use core::convert::TryInto;
 
 pub fn atoi(digits: &[u8]) -> u64 {
     let mut num = 0;
@@ -17,6 +18,9 @@ pub fn atoi(digits: &[u8]) -> u64 {
 
 const U64_MAX_LEN: usize = 20;
 
+// Room for a u64 followed by the longest unit suffix, such as "KiB"
+const BUFFER_LEN: usize = U64_MAX_LEN + 3;
+
 const DEC_DIGITS_LUT: &[u8] = b"\
       0001020304050607080910111213141516171819\
       2021222324252627282930313233343536373839\
@@ -25,23 +29,81 @@ const DEC_DIGITS_LUT: &[u8] = b"\
       8081828384858687888990919293949596979899";
 
 pub struct Buffer {
-    bytes: [u8; U64_MAX_LEN],
+    bytes: [u8; BUFFER_LEN],
 }
 
 impl Buffer {
     pub fn new() -> Buffer {
         Buffer {
-            bytes: [0u8; U64_MAX_LEN],
+            bytes: [0u8; BUFFER_LEN],
+        }
+    }
+
+    pub fn format(&mut self, n: u64) -> &[u8] {
+        let start = self.format_before(n, BUFFER_LEN);
+        &self.bytes[start..]
+    }
+
+    /// Format a number of bytes in units of the block size, rounding up like GNU ls does
+    pub fn format_size(&mut self, bytes: u64, block_size: BlockSize) -> &[u8] {
+        match block_size {
+            BlockSize::Fixed {
+                bytes: unit,
+                suffix,
+            } => {
+                let end = BUFFER_LEN - suffix.len();
+                self.bytes[end..].copy_from_slice(suffix);
+                let start = self.format_before(bytes.div_ceil(unit), end);
+                &self.bytes[start..]
+            }
+            BlockSize::Human { base } => self.format_human(bytes, base),
         }
     }
 
-    pub fn format(&mut self, mut n: u64) -> &[u8] {
+    /// Format a number of bytes with the largest unit that keeps it below the base, like 4.2K.
+    /// Just like GNU ls, values under 10 get one decimal place and everything is rounded up.
+    fn format_human(&mut self, bytes: u64, base: u64) -> &[u8] {
+        if bytes < base {
+            return self.format(bytes);
+        }
+        let units = if base == 1000 { b"kMGTPE" } else { b"KMGTPE" };
+        let (bytes, base) = (u128::from(bytes), u128::from(base));
+        let mut exponent = 0;
+        let mut divisor = base;
+        while bytes >= divisor * base && exponent + 1 < units.len() {
+            divisor *= base;
+            exponent += 1;
+        }
+
+        let end = BUFFER_LEN - 1;
+        loop {
+            self.bytes[end] = units[exponent];
+            let tenths = (bytes * 10).div_ceil(divisor);
+            if tenths < 100 {
+                self.bytes[end - 1] = b'0' + (tenths % 10) as u8;
+                self.bytes[end - 2] = b'.';
+                let start = self.format_before((tenths / 10) as u64, end - 2);
+                return &self.bytes[start..];
+            }
+            // Rounding up can carry into the next unit, as in 1023.1K becoming 1.0M
+            let whole = bytes.div_ceil(divisor);
+            if whole >= base && exponent + 1 < units.len() {
+                divisor *= base;
+                exponent += 1;
+                continue;
+            }
+            let start = self.format_before(whole as u64, end);
+            return &self.bytes[start..];
+        }
+    }
+
+    fn format_before(&mut self, mut n: u64, end: usize) -> usize {
         let buf = &mut self.bytes;
-        let mut curr = buf.len();
+        let mut curr = end;
 
         if n == 0 {
             buf[curr - 1] = b'0';
-            return &buf[curr - 1..];
+            return curr - 1;
         }
 
         while n >= 10 {
@@ -56,7 +118,113 @@ impl Buffer {
             buf[curr] = (n as u8) + b'0';
         }
 
-        &buf[curr..]
+        curr
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BlockSize {
+    Fixed {
+        bytes: u64,
+        /// Printed after every size when the block size was specified as only a unit, like "K"
+        suffix: &'static [u8],
+    },
+    /// -h and --si, which pick a unit for each size in powers of 1024 or 1000
+    Human { base: u64 },
+}
+
+impl BlockSize {
+    pub const BYTES: Self = Self::new(1);
+
+    pub const fn new(bytes: u64) -> Self {
+        Self::Fixed { bytes, suffix: b"" }
+    }
+
+    /// Parses the SIZE argument to --block-size and the BLOCK_SIZE family of environment
+    /// variables: an optional integer followed by an optional unit such as K, KiB, or KB, or
+    /// human-readable or si.
+    pub fn parse(spec: &'static [u8]) -> Option<Self> {
+        match spec {
+            b"human-readable" => return Some(Self::Human { base: 1024 }),
+            b"si" => return Some(Self::Human { base: 1000 }),
+            _ => {}
+        }
+        // A leading ' requests thousands separators, which we never print
+        let spec = spec.strip_prefix(b"'").unwrap_or(spec);
+        let digits = spec.iter().take_while(|b| b.is_ascii_digit()).count();
+        let (number, unit) = spec.split_at(digits);
+        if number.is_empty() && unit.is_empty() || digits > 19 {
+            return None;
+        }
+
+        let exponent = match unit.first() {
+            None => 0,
+            Some(b'k' | b'K') => 1,
+            Some(b'M') => 2,
+            Some(b'G') => 3,
+            Some(b'T') => 4,
+            Some(b'P') => 5,
+            Some(b'E') => 6,
+            Some(_) => return None,
+        };
+        let base: u64 = match unit.get(1..).unwrap_or_default() {
+            b"" | b"iB" => 1024,
+            b"B" => 1000,
+            _ => return None,
+        };
+        let multiplier = base.pow(exponent);
+        let bytes = if number.is_empty() {
+            multiplier
+        } else {
+            atoi(number).checked_mul(multiplier)?
+        };
+        if bytes == 0 {
+            return None;
+        }
+
+        let suffix = if !number.is_empty() {
+            b""
+        } else if unit == b"KB" {
+            b"kB"
+        } else {
+            unit
+        };
+        Some(Self::Fixed { bytes, suffix })
+    }
+}
+
+/// Reads big-endian binary formats, like TZif files and the git index, from the front of a slice,
+/// and looks for the runs of digits that version sorting compares as numbers
+pub trait SliceExt: Sized {
+    fn read_u16_be(&mut self) -> Option<u16>;
+    fn read_u32_be(&mut self) -> Option<u32>;
+    fn read(&mut self, n: usize) -> Option<Self>;
+    fn digit_at(&self, index: usize) -> bool;
+}
+
+impl SliceExt for &[u8] {
+    fn read(&mut self, n: usize) -> Option<Self> {
+        if self.len() < n {
+            None
+        } else {
+            let (head, tail) = self.split_at(n);
+            *self = tail;
+            Some(head)
+        }
+    }
+
+    fn read_u16_be(&mut self) -> Option<u16> {
+        let bytes = self.read(2)?;
+        Some(u16::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_u32_be(&mut self) -> Option<u32> {
+        let bytes = self.read(4)?;
+        Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn digit_at(&self, index: usize) -> bool {
+        self.get(index).map(u8::is_ascii_digit).unwrap_or(false)
     }
 }
 
@@ -71,14 +239,85 @@ pub fn memcmp(aa: &[u8], bb: &[u8]) -> core::cmp::Ordering {
     aa.len().cmp(&bb.len())
 }
 
+pub fn memcmp_ignore_case(aa: &[u8], bb: &[u8]) -> core::cmp::Ordering {
+    for (a, b) in aa.iter().zip(bb.iter()) {
+        if !a.eq_ignore_ascii_case(b) {
+            return a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase());
+        }
+    }
+    aa.len().cmp(&bb.len())
+}
+
 pub fn fs_read(path: CStr<'_>) -> Result<Vec<u8>, crate::Error> {
-    let fd = openat(libc::AT_FDCWD, path, OpenFlags::RDONLY, OpenMode::empty())?;
+    fs_read_at(libc::AT_FDCWD, path)
+}
+
+/// Like fs_read, but relative paths are looked up in the directory dirfd refers to
+pub fn fs_read_at(dirfd: libc::c_int, path: CStr<'_>) -> Result<Vec<u8>, crate::Error> {
+    let fd = openat(dirfd, path, OpenFlags::RDONLY, OpenMode::empty())?;
     let len = fstat(fd)?.st_size as usize;
     let mut contents = alloc::vec![0; len];
     let mut bytes_read = 0;
     while bytes_read < len {
         bytes_read += read(fd, &mut contents[bytes_read..])?;
     }
+    // Files in /proc report a size of 0, so those must be read until EOF
+    if len == 0 {
+        loop {
+            contents.resize(bytes_read + 4096, 0);
+            let n = read(fd, &mut contents[bytes_read..])?;
+            if n == 0 {
+                break;
+            }
+            bytes_read += n;
+        }
+        contents.truncate(bytes_read);
+    }
     close(fd)?;
     Ok(contents)
 }
+
+/// A whole file mapped into memory, for files that may be too big to read in
+pub struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    pub fn new(path: CStr<'_>) -> Result<Self, crate::Error> {
+        let fd = openat(libc::AT_FDCWD, path, OpenFlags::RDONLY, OpenMode::empty())?;
+        let len = fstat(fd)?.st_size as usize;
+        let ptr = if len == 0 {
+            Ok(core::ptr::NonNull::dangling().as_ptr())
+        } else {
+            crate::syscalls::mmap(
+                core::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                fd,
+                0,
+            )
+        };
+        close(fd)?;
+        Ok(Self { ptr: ptr?, len })
+    }
+}
+
+impl core::ops::Deref for Mapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe {
+                let _ = crate::syscalls::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
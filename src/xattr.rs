@@ -0,0 +1,249 @@
+//! Extended attributes, which is where SELinux contexts, POSIX ACLs and file capabilities are kept
+
+use crate::syscalls;
+use alloc::vec::Vec;
+use veneer::{CStr, Error};
+
+/// What --xattrs shows under each entry in long format
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum XattrListing {
+    /// The name and size of each attribute
+    Names,
+    /// The same, then the value
+    Values,
+}
+
+/// Reads the SELinux context of an entry of a directory. This is just the security.selinux
+/// attribute, so we don't need libselinux.
+pub fn selinux_context(dirfd: libc::c_int, name: CStr, follow_symlinks: bool) -> Option<Vec<u8>> {
+    let mut context = get(dirfd, name, SELINUX, follow_symlinks)?;
+    // The kernel hands us the null terminator too
+    if context.last() == Some(&0) {
+        context.pop();
+    }
+    Some(context).filter(|context| !context.is_empty())
+}
+
+/// Whether an entry has file capabilities, which GNU ls colors with the ca key
+pub fn has_capabilities(dirfd: libc::c_int, name: CStr, follow_symlinks: bool) -> bool {
+    size(dirfd, name, CAPABILITY, follow_symlinks).is_some()
+}
+
+pub const SELINUX: &[u8] = b"security.selinux\0";
+pub const CAPABILITY: &[u8] = b"security.capability\0";
+
+/// Whether a null-terminated attribute is among the names from list
+pub fn contains(names: &[u8], attribute: &[u8]) -> bool {
+    names
+        .split_inclusive(|b| *b == 0)
+        .any(|name| name == attribute)
+}
+
+/// Decodes an entry's file capabilities into the names getcap uses, like cap_net_raw+ep.
+/// Capabilities with the same flags are grouped together, as in cap_chown,cap_kill+p.
+pub fn capabilities(dirfd: libc::c_int, name: CStr, follow_symlinks: bool) -> Option<Vec<u8>> {
+    use crate::utils::SliceExt;
+    use core::convert::TryInto;
+
+    let data = get(dirfd, name, CAPABILITY, follow_symlinks)?;
+    let mut data = &data[..];
+    let mut read_u32_le = || Some(u32::from_le_bytes(data.read(4)?.try_into().ok()?));
+    let magic = read_u32_le()?;
+    // The sets are split into 32-bit words, each with the permitted then inheritable bits
+    let words = match magic & VFS_CAP_REVISION_MASK {
+        VFS_CAP_REVISION_1 => 1,
+        VFS_CAP_REVISION_2 | VFS_CAP_REVISION_3 => 2,
+        _ => return None,
+    };
+    let effective = magic & VFS_CAP_FLAGS_EFFECTIVE != 0;
+    let (mut permitted, mut inheritable) = (0u64, 0u64);
+    for word in 0..words {
+        permitted |= u64::from(read_u32_le()?) << (32 * word);
+        inheritable |= u64::from(read_u32_le()?) << (32 * word);
+    }
+
+    let flags = |cap: u32| {
+        let (p, i) = (permitted >> cap & 1 != 0, inheritable >> cap & 1 != 0);
+        [(effective && (p || i), b'e'), (i, b'i'), (p, b'p')]
+    };
+    let mut text = Vec::new();
+    let mut done = 0u64;
+    for cap in 0..64 {
+        if done >> cap & 1 != 0 || (permitted | inheritable) >> cap & 1 == 0 {
+            continue;
+        }
+        if !text.is_empty() {
+            text.push(b' ');
+        }
+        // Every later capability with the same flags goes in this group
+        let group = flags(cap);
+        for other in (cap..64).filter(|other| flags(*other) == group) {
+            if other != cap {
+                text.push(b',');
+            }
+            match CAPABILITY_NAMES.get(other as usize) {
+                Some(name) => text.extend_from_slice(name),
+                None => text.extend_from_slice(crate::utils::Buffer::new().format(other.into())),
+            }
+            done |= 1 << other;
+        }
+        text.push(b'+');
+        text.extend(group.iter().filter(|f| f.0).map(|f| f.1));
+    }
+    Some(text)
+}
+
+const VFS_CAP_REVISION_MASK: u32 = 0xFF000000;
+const VFS_CAP_REVISION_1: u32 = 0x01000000;
+const VFS_CAP_REVISION_2: u32 = 0x02000000;
+const VFS_CAP_REVISION_3: u32 = 0x03000000;
+const VFS_CAP_FLAGS_EFFECTIVE: u32 = 0x000001;
+
+/// From linux/capability.h, indexed by capability number
+static CAPABILITY_NAMES: &[&[u8]] = &[
+    b"cap_chown",
+    b"cap_dac_override",
+    b"cap_dac_read_search",
+    b"cap_fowner",
+    b"cap_fsetid",
+    b"cap_kill",
+    b"cap_setgid",
+    b"cap_setuid",
+    b"cap_setpcap",
+    b"cap_linux_immutable",
+    b"cap_net_bind_service",
+    b"cap_net_broadcast",
+    b"cap_net_admin",
+    b"cap_net_raw",
+    b"cap_ipc_lock",
+    b"cap_ipc_owner",
+    b"cap_sys_module",
+    b"cap_sys_rawio",
+    b"cap_sys_chroot",
+    b"cap_sys_ptrace",
+    b"cap_sys_pacct",
+    b"cap_sys_admin",
+    b"cap_sys_boot",
+    b"cap_sys_nice",
+    b"cap_sys_resource",
+    b"cap_sys_time",
+    b"cap_sys_tty_config",
+    b"cap_mknod",
+    b"cap_lease",
+    b"cap_audit_write",
+    b"cap_audit_control",
+    b"cap_setfcap",
+    b"cap_mac_override",
+    b"cap_mac_admin",
+    b"cap_syslog",
+    b"cap_wake_alarm",
+    b"cap_block_suspend",
+    b"cap_audit_read",
+    b"cap_perfmon",
+    b"cap_bpf",
+    b"cap_checkpoint_restore",
+];
+
+/// The character GNU ls puts after the permissions in long format: + if there's an ACL, or . if
+/// there's just an SELinux context. names is what list found.
+pub fn mode_indicator(names: &[u8]) -> Option<u8> {
+    let mut indicator = None;
+    for attribute in names.split(|b| *b == 0) {
+        match attribute {
+            b"system.posix_acl_access" | b"system.posix_acl_default" => return Some(b'+'),
+            b"security.selinux" => indicator = Some(b'.'),
+            _ => {}
+        }
+    }
+    indicator
+}
+
+/// The names of an entry's attributes, each followed by a null byte
+pub fn list(dirfd: libc::c_int, name: CStr, follow_symlinks: bool) -> Result<Vec<u8>, Error> {
+    read(|buf| {
+        at(dirfd, name, |path| {
+            if follow_symlinks {
+                syscalls::listxattr(path, buf)
+            } else {
+                syscalls::llistxattr(path, buf)
+            }
+        })
+    })
+}
+
+/// How big an attribute's value is, without reading it. attribute must be null-terminated.
+pub fn size(
+    dirfd: libc::c_int,
+    name: CStr,
+    attribute: &[u8],
+    follow_symlinks: bool,
+) -> Option<usize> {
+    let attribute = CStr::from_bytes(attribute);
+    at(dirfd, name, |path| {
+        if follow_symlinks {
+            syscalls::getxattr(path, attribute, &mut [])
+        } else {
+            syscalls::lgetxattr(path, attribute, &mut [])
+        }
+    })
+    .ok()
+}
+
+/// Reads an attribute's value. attribute must be null-terminated.
+pub fn get(
+    dirfd: libc::c_int,
+    name: CStr,
+    attribute: &[u8],
+    follow_symlinks: bool,
+) -> Option<Vec<u8>> {
+    let attribute = CStr::from_bytes(attribute);
+    read(|buf| {
+        at(dirfd, name, |path| {
+            if follow_symlinks {
+                syscalls::getxattr(path, attribute, buf)
+            } else {
+                syscalls::lgetxattr(path, attribute, buf)
+            }
+        })
+    })
+    .ok()
+}
+
+/// Calls one of the xattr syscalls, which fail with ERANGE if the buffer is too small and say how
+/// big it needs to be if it's empty
+fn read(mut read: impl FnMut(&mut [u8]) -> Result<usize, Error>) -> Result<Vec<u8>, Error> {
+    let mut buf = [0u8; 256];
+    match read(&mut buf) {
+        Ok(len) => Ok(buf[..len].to_vec()),
+        // Too big for buf, so ask how big it is
+        Err(Error(libc::ERANGE)) => {
+            let mut value = alloc::vec![0u8; read(&mut [])?];
+            let len = read(&mut value)?;
+            value.truncate(len);
+            Ok(value)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// There's no getxattrat before Linux 6.13, and f*xattr don't take O_PATH file descriptors, so
+/// the xattr syscalls need a path to the entry, which we get through the directory's file
+/// descriptor in /proc. Without /proc, as in some containers and chroots, this fails and the
+/// entry is shown without its attributes.
+fn at(
+    dirfd: libc::c_int,
+    name: CStr,
+    call: impl FnOnce(CStr) -> Result<usize, Error>,
+) -> Result<usize, Error> {
+    let name_bytes = name.as_bytes();
+    if dirfd == libc::AT_FDCWD || name_bytes.starts_with(b"/") {
+        return call(name);
+    }
+    let mut path = Vec::with_capacity(name_bytes.len() + 24);
+    path.extend_from_slice(b"/proc/self/fd/");
+    path.extend_from_slice(crate::utils::Buffer::new().format(dirfd as u64));
+    path.push(b'/');
+    path.extend_from_slice(name_bytes);
+    path.push(0);
+    call(CStr::from_bytes(&path))
+}
@@ -0,0 +1,148 @@
+import json
+import os
+import subprocess
+import tempfile
+
+p = subprocess.run(['cargo', 'build', '--message-format=json'],
+                   stdout=subprocess.PIPE,
+                   check=True)
+
+messages = [m for m in p.stdout.splitlines() if len(m) > 0]
+exe_location = json.loads(messages[-1])['executable']
+
+
+def git(repo, *args):
+    subprocess.run(['git', '-C', repo, '-c', 'user.name=fls', '-c', 'user.email=fls@example.com',
+                    *args],
+                   stdout=subprocess.DEVNULL,
+                   check=True)
+
+
+def staged_by_git(repo, directory):
+    """The staged letter git gives each file directly inside directory"""
+    output = subprocess.run(['git', '-C', repo, 'status', '--porcelain', '--untracked-files=no'],
+                            stdout=subprocess.PIPE,
+                            check=True).stdout.decode()
+    staged = {}
+    for line in output.splitlines():
+        path = line[3:]
+        parent, _, name = path.rpartition('/')
+        if parent == directory and line[0] != ' ':
+            staged[name] = line[0]
+    return staged
+
+
+def staged_by_fls(repo, directory):
+    output = subprocess.run([exe_location, '-1', '--git', os.path.join(repo, directory)],
+                            stdout=subprocess.PIPE,
+                            check=True).stdout.decode()
+    staged = {}
+    for line in output.splitlines():
+        status, name = line.split(' ', 1)
+        path = os.path.join(repo, directory, name)
+        if status[0] not in '-?!' and not os.path.isdir(path):
+            staged[name] = status[0]
+    return staged
+
+
+def check(case, repo, directory='d'):
+    expected = staged_by_git(repo, directory)
+    # Files that were deleted from the index aren't there to be listed
+    expected = {k: v for (k, v) in expected.items() if v != 'D'}
+    actual = staged_by_fls(repo, directory)
+    if expected != actual:
+        print(case, 'differs:')
+        print('git:', sorted(expected.items()))
+        print('fls:', sorted(actual.items()))
+
+
+with tempfile.TemporaryDirectory() as repo:
+    git(repo, 'init', '-q')
+    os.makedirs(os.path.join(repo, 'd', 'e'))
+    # Enough history that packing turns the trees into chains of deltas
+    for i in range(60):
+        for j in range(i % 7 + 1):
+            with open(os.path.join(repo, 'd', 'f{}'.format(i * j % 40)), 'w') as f:
+                f.write('{} {}\n'.format(i, j))
+        with open(os.path.join(repo, 'd', 'e', 'g{}'.format(i % 5)), 'w') as f:
+            f.write('{}\n'.format(i))
+        git(repo, 'add', '-A')
+        git(repo, 'commit', '-q', '-m', str(i))
+
+    check('loose objects', repo)
+    for back in [1, 5, 30]:
+        git(repo, 'reset', '-q', '--soft', 'HEAD~{}'.format(back))
+        check('loose objects, reset --soft HEAD~{}'.format(back), repo)
+        git(repo, 'reset', '-q', '--soft', 'ORIG_HEAD')
+
+    git(repo, 'gc', '-q', '--aggressive')
+    for back in [1, 5, 30]:
+        git(repo, 'reset', '-q', '--soft', 'HEAD~{}'.format(back))
+        check('packed objects, reset --soft HEAD~{}'.format(back), repo)
+        check('packed objects, reset --soft HEAD~{}, subdirectory'.format(back), repo, 'd/e')
+        git(repo, 'reset', '-q', '--soft', 'ORIG_HEAD')
+
+    with open(os.path.join(repo, 'd', 'new'), 'w') as f:
+        f.write('new\n')
+    git(repo, 'add', 'd/new')
+    git(repo, 'rm', '-q', '--cached', 'd/f1')
+    check('added and removed', repo)
+
+with tempfile.TemporaryDirectory() as repo:
+    git(repo, 'init', '-q')
+    os.makedirs(os.path.join(repo, 'd'))
+    with open(os.path.join(repo, 'd', 'a'), 'w') as f:
+        f.write('a\n')
+    git(repo, 'add', 'd/a')
+    check('no commits yet', repo)
+
+# A damaged object must not be mistaken for anything, so nothing is shown as staged
+with tempfile.TemporaryDirectory() as repo:
+    git(repo, 'init', '-q')
+    os.makedirs(os.path.join(repo, 'd'))
+    with open(os.path.join(repo, 'd', 'a'), 'w') as f:
+        f.write('a\n')
+    git(repo, 'add', 'd/a')
+    git(repo, 'commit', '-q', '-m', 'a')
+    tree = subprocess.run(['git', '-C', repo, 'rev-parse', 'HEAD^{tree}'],
+                          stdout=subprocess.PIPE,
+                          check=True).stdout.decode().strip()
+    path = os.path.join(repo, '.git', 'objects', tree[:2], tree[2:])
+    os.chmod(path, 0o644)
+    with open(path, 'r+b') as f:
+        contents = bytearray(f.read())
+        # The last byte is part of the checksum, so the tree still decompresses
+        contents[-1] ^= 0xff
+        f.seek(0)
+        f.write(contents)
+    # Without the cache tree, the damaged tree has to be read
+    with open(os.path.join(repo, 'd', 'a'), 'w') as f:
+        f.write('b\n')
+    os.remove(os.path.join(repo, '.git', 'index'))
+    git(repo, 'update-index', '--add', 'd/a')
+    actual = staged_by_fls(repo, 'd')
+    if actual:
+        print('damaged tree differs:')
+        print('fls:', sorted(actual.items()))
+
+# Untracked files roll up into every directory above them, ignored ones don't
+with tempfile.TemporaryDirectory() as repo:
+    git(repo, 'init', '-q')
+    os.makedirs(os.path.join(repo, 'a', 'b', 'c'))
+    os.makedirs(os.path.join(repo, 'x', 'y'))
+    for path in ['a/t', 'a/b/c/u', 'x/t', 'x/y/i.o']:
+        with open(os.path.join(repo, path), 'w') as f:
+            f.write('{}\n'.format(path))
+    with open(os.path.join(repo, '.gitignore'), 'w') as f:
+        f.write('*.o\n')
+    git(repo, 'add', 'a/t', 'x/t', '.gitignore')
+    git(repo, 'commit', '-q', '-m', 'a')
+    output = subprocess.run([exe_location, '-1', '--git', repo],
+                            stdout=subprocess.PIPE,
+                            check=True).stdout.decode()
+    actual = dict(reversed(line.split(' ', 1)) for line in output.splitlines())
+    expected = {'a': '-?', 'x': '--'}
+    if actual != expected:
+        print('untracked roll-up differs:')
+        print('expected:', sorted(expected.items()))
+        print('fls:', sorted(actual.items()))
@@ -10,13 +10,13 @@ p = subprocess.run(['cargo', 'build', '--message-format=json'],
 messages = [m for m in p.stdout.splitlines() if len(m) > 0]
 exe_location = json.loads(messages[-1])['executable']
 
-sort_orders = ['-c', '-t', '-f', '-rc', '-rt', '-rf']
+sort_orders = ['-c', '-t', '-f', '-rc', '-rt', '-rf', '--sort=time', '--sort=size', '--reverse', '-X', '--sort=width', '-B', '--ignore=*.rs', '--hide=*.md', '-U', '-U --group-directories-first', '-f --group-directories-first']
 
 for switches in sort_orders:
-    fls_output = subprocess.run([exe_location, switches],
+    fls_output = subprocess.run([exe_location, *switches.split()],
                                 stdout=subprocess.PIPE,
                                 check=True).stdout
-    gnuls_output = subprocess.run(['/bin/ls', switches],
+    gnuls_output = subprocess.run(['/bin/ls', *switches.split()],
                                   stdout=subprocess.PIPE,
                                   check=True).stdout
 
@@ -28,7 +28,7 @@ for switches in sort_orders:
             if not line.startswith(' '):
                 print(line)
 
-long_modes = ['-l', '-n', '-o', '-ln', '-lo', '-li', '-nl', '-ol', '-il']
+long_modes = ['-l', '-n', '-o', '-ln', '-lo', '-li', '-nl', '-ol', '-il', '-ls', '-lsk', '-lh', '-lsh', '-lQ', '--full-time']
 for switches in long_modes:
     fls_output = subprocess.run([exe_location, '-f', '..', switches],
                                 stdout=subprocess.PIPE,
@@ -44,3 +44,20 @@ for switches in long_modes:
                 print('fls:', f)
                 print('gnu:', g)
                 print()
+
+# GNU ls has no --tree, but with -a the tree should hold the same names as a recursive listing,
+# and in particular must not climb out through ..
+fls_output = subprocess.run([exe_location, '--tree', '-a', 'src'],
+                            stdout=subprocess.PIPE,
+                            check=True,
+                            timeout=10).stdout
+gnuls_output = subprocess.run(['/bin/ls', '-1aR', 'src'],
+                              stdout=subprocess.PIPE,
+                              check=True).stdout
+fls_names = sorted(l.lstrip('|`-│├└─ ') for l in fls_output.decode().splitlines()[1:])
+gnuls_names = sorted(l for l in gnuls_output.decode().splitlines() if l and not l.endswith(':'))
+if fls_names != gnuls_names:
+    print('--tree -a differs:')
+    for line in difflib.Differ().compare(gnuls_names, fls_names):
+        if not line.startswith(' '):
+            print(line)